@@ -1,24 +1,151 @@
 use std::fmt;
 use std::sync::Arc;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
 
 use futures::stream::{FuturesUnordered, StreamExt};
-use reqwest::Client;
-use tokio::sync::Semaphore;
+use reqwest::header::HeaderMap;
+use reqwest::StatusCode;
 use tokio::sync::mpsc;
 use tracing::error;
+use url::Url;
 
 use crate::config::AppConfig;
-use crate::client::get_with_retry;
+use crate::client::{build_status_client, get_with_retry, Pacing};
+use crate::extractor::{Directive, ResourceKind};
+use crate::pipeline::max_in_flight;
+use crate::reconcile::Coverage;
+use crate::retry::RetryPolicy;
+use crate::robots::{RobotsCache, BLOCKED_STATUS_TEXT};
+use crate::scheduler::{HostScheduler, CIRCUIT_OPEN_STATUS_TEXT};
+use crate::sitemap_parser::SitemapEntry;
+use crate::state::StateStore;
 
 use rand::Rng;
 
+/// A URL queued for checking, with what is known about it before the request.
+#[derive(Debug, Clone)]
+pub struct CheckTarget {
+    pub url: String,
+    /// Click depth from the crawl start URL; `None` for sitemap URLs and
+    /// pages not reachable from it.
+    pub depth: Option<u32>,
+    /// Other spellings of this URL that normalized to it.
+    pub aliases: Vec<String>,
+    pub kind: ResourceKind,
+    /// Robots/link directives that applied to this URL while crawling.
+    pub directives: Vec<Directive>,
+    /// Sitemap vs. crawl coverage; only set in hybrid mode.
+    pub coverage: Option<Coverage>,
+    /// What the sitemap says about this URL, when it is listed in one.
+    pub sitemap: Option<SitemapEntry>,
+    /// The crawler's own response, when it already fetched this URL.
+    pub fetched: Option<Outcome>,
+}
+
+/// What requesting a URL came back with, whoever made the request.
+#[derive(Debug, Clone)]
+pub struct Outcome {
+    pub status_code: Option<u16>,
+    pub status_text: String,
+    pub redirect_url: Option<String>,
+    /// The response's `Content-Type` header, when it had one.
+    pub content_type: Option<String>,
+    /// Retries it took to get this outcome.
+    pub retries: u32,
+    /// Time to the response headers; `None` when there was no response.
+    pub response_time: Option<Duration>,
+}
+
+impl Outcome {
+    pub fn response(status: StatusCode, headers: &HeaderMap, retries: u32, elapsed: Duration) -> Self {
+        let redirect_url = if status.is_redirection() {
+            headers
+                .get(reqwest::header::LOCATION)
+                .and_then(|v| v.to_str().ok())
+                .map(|s| s.to_string())
+        } else {
+            None
+        };
+        let content_type = headers
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .map(|s| s.to_string());
+
+        Self {
+            status_code: Some(status.as_u16()),
+            status_text: status.canonical_reason().unwrap_or("Unknown").to_string(),
+            redirect_url,
+            content_type,
+            retries,
+            response_time: Some(elapsed),
+        }
+    }
+
+    pub fn error(e: &reqwest::Error, retries: u32) -> Self {
+        let text = if e.is_timeout() {
+            "Timeout".to_string()
+        } else if e.is_connect() {
+            "Connection Error".to_string()
+        } else {
+            format!("{}", e)
+        };
+        Self::without_request(text, retries)
+    }
+
+    /// A status that was decided without getting a response, e.g. blocked by robots.txt.
+    pub fn without_request(text: impl Into<String>, retries: u32) -> Self {
+        Self {
+            status_code: None,
+            status_text: text.into(),
+            redirect_url: None,
+            content_type: None,
+            retries,
+            response_time: None,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct UrlStatus {
     pub url: String,
     pub status_code: Option<u16>,
     pub status_text: String,
     pub redirect_url: Option<String>,
+    /// The response's `Content-Type` header, when it had one.
+    pub content_type: Option<String>,
+    pub depth: Option<u32>,
+    pub aliases: Vec<String>,
+    pub kind: ResourceKind,
+    /// Robots/link directives that applied to this URL while crawling.
+    pub directives: Vec<Directive>,
+    /// Sitemap vs. crawl coverage; only set in hybrid mode.
+    pub coverage: Option<Coverage>,
+    /// What the sitemap says about this URL, when it is listed in one.
+    pub sitemap: Option<SitemapEntry>,
+    /// Retries it took to get this status.
+    pub retries: u32,
+    /// Time to the response headers; `None` when there was no response.
+    pub response_time: Option<Duration>,
+}
+
+impl UrlStatus {
+    pub fn new(target: CheckTarget, outcome: Outcome) -> Self {
+        Self {
+            url: target.url,
+            status_code: outcome.status_code,
+            status_text: outcome.status_text,
+            redirect_url: outcome.redirect_url,
+            content_type: outcome.content_type,
+            depth: target.depth,
+            aliases: target.aliases,
+            kind: target.kind,
+            directives: target.directives,
+            coverage: target.coverage,
+            sitemap: target.sitemap,
+            retries: outcome.retries,
+            response_time: outcome.response_time,
+        }
+    }
 }
 
 impl fmt::Display for UrlStatus {
@@ -36,85 +163,89 @@ impl fmt::Display for UrlStatus {
     }
 }
 
+/// Check every target received on `targets` until it closes, sending each status to
+/// `tx`. Pages the crawler already fetched, and targets a previous run already
+/// checked (per `state`), are sent straight from that response without a request.
+///
+/// Only a bounded number of checks exist at a time; the next target is not taken
+/// until one finishes, so memory stays flat however many URLs come through.
 pub async fn check_urls_stream(
-    urls: &[String],
+    mut targets: mpsc::Receiver<CheckTarget>,
     config: &AppConfig,
-    tx: mpsc::UnboundedSender<UrlStatus>,
+    robots: Option<Arc<RobotsCache>>,
+    scheduler: HostScheduler,
+    state: Option<Arc<StateStore>>,
+    tx: mpsc::Sender<UrlStatus>,
 ) {
-    let mut builder = Client::builder()
-        .timeout(config.timeout_duration())
-        .redirect(reqwest::redirect::Policy::none());
-
-    if let Some(p) = &config.proxy {
-        builder = builder.proxy(reqwest::Proxy::all(p).expect("Invalid proxy URL"));
-    }
-
-    let client = builder.build().expect("Failed to build HTTP client");
+    let client = build_status_client(config.proxy.as_deref(), config.timeout_duration())
+        .expect("Failed to build HTTP client");
+    let retry = RetryPolicy::from_config(config);
 
-    let semaphore = Arc::new(Semaphore::new(config.concurrency));
     let delay_ms = config.delay;
+    let max_in_flight = max_in_flight(config.concurrency);
     let mut futures = FuturesUnordered::new();
 
-    let completed = Arc::new(AtomicUsize::new(0));
+    while let Some(mut target) = targets.recv().await {
+        if let Some(outcome) = target.fetched.take() {
+            let status = UrlStatus::new(target, outcome);
+            if let Some(state) = &state {
+                state.record_status(&status);
+            }
+            let _ = tx.send(status).await;
+            continue;
+        }
+        if let Some(saved) = state.as_ref().and_then(|s| s.checked(&target.url)) {
+            let _ = tx.send(UrlStatus::new(target, saved.clone())).await;
+            continue;
+        }
+
+        while futures.len() >= max_in_flight {
+            if let Some(Err(e)) = futures.next().await {
+                error!(error = %e, "Task panicked");
+            }
+        }
 
-    for url in urls.iter() {
         let client = client.clone();
-        let sem = Arc::clone(&semaphore);
-        let url = url.clone();
-        let completed = Arc::clone(&completed);
+        let scheduler = scheduler.clone();
         let tx = tx.clone();
-        let max_retries = config.retry;
+        let robots = robots.clone();
+        let state = state.clone();
+        let retry = retry.clone();
 
         futures.push(tokio::spawn(async move {
-            let _permit = sem.acquire().await.expect("semaphore closed");
+            // Waiting out a Crawl-delay must not hold one of the host's slots.
+            if let (Some(robots), Ok(parsed)) = (&robots, Url::parse(&target.url)) {
+                if !robots.is_allowed(&parsed).await {
+                    let status = UrlStatus::new(target, Outcome::without_request(BLOCKED_STATUS_TEXT, 0));
+                    if let Some(state) = &state {
+                        state.record_status(&status);
+                    }
+                    let _ = tx.send(status).await;
+                    return;
+                }
+                robots.wait_turn(&parsed).await;
+            }
+
+            // Not saved to the state store, so a resumed run checks these again.
+            let Ok(permit) = scheduler.acquire(&target.url).await else {
+                let _ = tx.send(UrlStatus::new(target, Outcome::without_request(CIRCUIT_OPEN_STATUS_TEXT, 0))).await;
+                return;
+            };
 
             let random_delay = rand::rng().random_range(delay_ms..=delay_ms * 3);
             tokio::time::sleep(std::time::Duration::from_millis(random_delay)).await;
 
-            let _done = completed.fetch_add(1, Ordering::Relaxed) + 1;
-
-            let status = match get_with_retry(&client, &url, max_retries).await {
-                Ok(resp) => {
-                    let code = resp.status().as_u16();
-                    let text = resp.status().canonical_reason()
-                        .unwrap_or("Unknown")
-                        .to_string();
-
-                    let redirect_url = if resp.status().is_redirection() {
-                        resp.headers()
-                            .get(reqwest::header::LOCATION)
-                            .and_then(|v| v.to_str().ok())
-                            .map(|s| s.to_string())
-                    } else {
-                        None
-                    };
-
-                    UrlStatus {
-                        url,
-                        status_code: Some(code),
-                        status_text: text,
-                        redirect_url,
-                    }
-                }
-                Err(e) => {
-                    let text = if e.is_timeout() {
-                        "Timeout".to_string()
-                    } else if e.is_connect() {
-                        "Connection Error".to_string()
-                    } else {
-                        format!("{}", e)
-                    };
-
-                    UrlStatus {
-                        url,
-                        status_code: None,
-                        status_text: text,
-                        redirect_url: None,
-                    }
-                }
+            let attempts = get_with_retry(&client, &target.url, &retry, Pacing::Permit(&permit)).await;
+            let outcome = match attempts.result {
+                Ok(resp) => Outcome::response(resp.status(), resp.headers(), attempts.retries, attempts.elapsed),
+                Err(e) => Outcome::error(&e, attempts.retries),
             };
+            let status = UrlStatus::new(target, outcome);
 
-            let _ = tx.send(status);
+            if let Some(state) = &state {
+                state.record_status(&status);
+            }
+            let _ = tx.send(status).await;
         }));
     }
 
@@ -1,13 +1,18 @@
-use reqwest::{Client, Response};
-use std::time::Duration;
-use tracing::warn;
+use std::time::{Duration, Instant};
+
+use reqwest::header::HeaderMap;
+use reqwest::{Client, Response, StatusCode};
+use tracing::{debug, warn};
 
 use crate::config::DEFAULT_TIMEOUT;
+use crate::retry::RetryPolicy;
+use crate::scheduler::{Permit, Signal};
+use crate::throttle::Throttle;
 use crate::user_agents::random_user_agent;
 
 pub fn build_client(proxy: Option<&str>) -> reqwest::Result<Client> {
     let mut builder = Client::builder()
-        .timeout(std::time::Duration::from_secs(DEFAULT_TIMEOUT));
+        .timeout(Duration::from_secs(DEFAULT_TIMEOUT));
         
     if let Some(p) = proxy {
         builder = builder.proxy(reqwest::Proxy::all(p)?);
@@ -16,57 +21,205 @@ pub fn build_client(proxy: Option<&str>) -> reqwest::Result<Client> {
     builder.build()
 }
 
-pub async fn get_with_retry(client: &Client, url: &str, max_retries: u32) -> Result<Response, reqwest::Error> {
+/// A client for bodies that are streamed while they are processed, such as
+/// sitemaps. A total timeout would cut off a large body whose reader is held
+/// back by slow consumers, so only connecting and each wait for more data are
+/// timed.
+pub fn build_stream_client(proxy: Option<&str>) -> reqwest::Result<Client> {
+    let timeout = Duration::from_secs(DEFAULT_TIMEOUT);
+    let mut builder = Client::builder()
+        .connect_timeout(timeout)
+        .read_timeout(timeout);
+
+    if let Some(p) = proxy {
+        builder = builder.proxy(reqwest::Proxy::all(p)?);
+    }
+
+    builder.build()
+}
+
+/// A client that reports redirects instead of following them, so the status
+/// recorded for a URL is its own.
+pub fn build_status_client(proxy: Option<&str>, timeout: Duration) -> reqwest::Result<Client> {
+    let mut builder = Client::builder()
+        .timeout(timeout)
+        .redirect(reqwest::redirect::Policy::none());
+
+    if let Some(p) = proxy {
+        builder = builder.proxy(reqwest::Proxy::all(p)?);
+    }
+
+    builder.build()
+}
+
+/// What paces a request: a scheduler permit, which carries the run-wide
+/// throttle along with its host's limits, or the run-wide throttle alone for
+/// requests made outside the scheduler, such as robots.txt and sitemaps.
+#[derive(Clone, Copy)]
+pub enum Pacing<'a> {
+    Permit(&'a Permit),
+    Throttle(&'a Throttle),
+}
+
+impl<'a> Pacing<'a> {
+    fn throttle(self) -> &'a Throttle {
+        match self {
+            Pacing::Permit(permit) => permit.throttle(),
+            Pacing::Throttle(throttle) => throttle,
+        }
+    }
+
+    fn permit(self) -> Option<&'a Permit> {
+        match self {
+            Pacing::Permit(permit) => Some(permit),
+            Pacing::Throttle(_) => None,
+        }
+    }
+}
+
+/// The final result of a request and how many retries it took.
+pub struct Attempts<T> {
+    pub result: Result<T, reqwest::Error>,
+    pub retries: u32,
+    /// Time to the response headers (or the error) on the last attempt.
+    pub elapsed: Duration,
+}
+
+/// A response with its body read, unless the caller did not want it.
+pub struct Fetched {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Option<Vec<u8>>,
+}
+
+/// GET `url`, retrying the statuses and network errors `policy` allows. Every
+/// attempt waits its turn under the run-wide request rate. With a scheduler
+/// permit, every attempt is also reported to the host's rate control, and
+/// retries stop once the host's circuit opens.
+pub async fn get_with_retry(
+    client: &Client,
+    url: &str,
+    policy: &RetryPolicy,
+    pacing: Pacing<'_>,
+) -> Attempts<Response> {
+    let permit = pacing.permit();
     let mut retries = 0;
-    let mut backoff_sec = 2;
+    let mut previous = None;
 
     loop {
+        pacing.throttle().request().await;
         let ua = random_user_agent();
-        let resp_result = client.get(url).header("User-Agent", ua).send().await;
-
-        match resp_result {
-            Ok(resp) => {
-                let status = resp.status();
-                // Check for 429 Too Many Requests or 5xx Server Errors
-                if (status == 429 || status.is_server_error()) && retries < max_retries {
-                    // Try to respect Retry-After header
-                    let retry_after = resp
-                        .headers()
-                        .get(reqwest::header::RETRY_AFTER)
-                        .and_then(|v| v.to_str().ok())
-                        .and_then(|v| v.parse::<u64>().ok())
-                        .unwrap_or(backoff_sec);
-
-                    // warn!(
-                    //     url = %url,
-                    //     status = %status,
-                    //     retry = retries + 1,
-                    //     retry_after_sec = retry_after,
-                    //     "Rate limited or server error, retrying..."
-                    // );
-                    tokio::time::sleep(Duration::from_secs(retry_after)).await;
-                    retries += 1;
-                    backoff_sec *= 2; // exponential backoff
-                    continue;
-                }
-                return Ok(resp);
+        let started = Instant::now();
+        let result = client.get(url).header("User-Agent", ua).send().await;
+        let elapsed = started.elapsed();
+        if let Some(permit) = permit {
+            permit.record(match &result {
+                Ok(resp) => Signal::from_status(resp.status()),
+                Err(e) => Signal::from_error(e),
+            });
+        }
+        let can_retry = retries < policy.max_retries && !permit.is_some_and(|p| p.circuit_open());
+
+        let wait = match &result {
+            Ok(resp) if can_retry && policy.retries_status(resp.status()) => {
+                let wait = policy
+                    .retry_after(resp.headers())
+                    .unwrap_or_else(|| policy.backoff(retries, previous));
+                debug!(
+                    url = %url,
+                    status = %resp.status(),
+                    retry = retries + 1,
+                    wait_ms = wait.as_millis() as u64,
+                    "Rate limited or server error, retrying..."
+                );
+                wait
+            }
+            Err(e) if can_retry && policy.retries_error(e) => {
+                let wait = policy.backoff(retries, previous);
+                warn!(
+                    url = %url,
+                    error = %e,
+                    retry = retries + 1,
+                    wait_ms = wait.as_millis() as u64,
+                    "Network error, retrying..."
+                );
+                wait
+            }
+            _ => return Attempts { result, retries, elapsed },
+        };
+
+        tokio::time::sleep(wait).await;
+        previous = Some(wait);
+        retries += 1;
+    }
+}
+
+/// Like `get_with_retry`, but also reads the body of responses `accept` wants,
+/// no faster than the bandwidth cap allows, retrying when the connection drops
+/// halfway through it.
+pub async fn get_body_with_retry(
+    client: &Client,
+    url: &str,
+    policy: &RetryPolicy,
+    pacing: Pacing<'_>,
+    accept: impl Fn(&Response) -> bool,
+) -> Attempts<Fetched> {
+    let permit = pacing.permit();
+    let mut retries = 0;
+
+    loop {
+        let remaining = RetryPolicy {
+            max_retries: policy.max_retries - retries,
+            ..policy.clone()
+        };
+        let attempts = get_with_retry(client, url, &remaining, pacing).await;
+        retries += attempts.retries;
+        let elapsed = attempts.elapsed;
+        let mut resp = match attempts.result {
+            Ok(resp) => resp,
+            Err(e) => return Attempts { result: Err(e), retries, elapsed },
+        };
+
+        let status = resp.status();
+        let headers = resp.headers().clone();
+        if !accept(&resp) {
+            let fetched = Fetched { status, headers, body: None };
+            return Attempts { result: Ok(fetched), retries, elapsed };
+        }
+
+        match read_body(&mut resp, pacing.throttle()).await {
+            Ok(body) => {
+                let fetched = Fetched { status, headers, body: Some(body) };
+                return Attempts { result: Ok(fetched), retries, elapsed };
             }
             Err(e) => {
-                if retries < max_retries && (e.is_timeout() || e.is_connect()) {
-                    warn!(
-                        url = %url,
-                        error = %e,
-                        retry = retries + 1,
-                        retry_after_sec = backoff_sec,
-                        "Network error, retrying..."
-                    );
-                    tokio::time::sleep(Duration::from_secs(backoff_sec)).await;
-                    retries += 1;
-                    backoff_sec *= 2;
-                    continue;
+                if let Some(permit) = permit {
+                    permit.record(Signal::from_error(&e));
+                }
+                let circuit_open = permit.is_some_and(|p| p.circuit_open());
+                if retries >= policy.max_retries || circuit_open || !policy.retries_error(&e) {
+                    return Attempts { result: Err(e), retries, elapsed };
                 }
-                return Err(e);
+                let wait = policy.backoff(retries, None);
+                warn!(
+                    url = %url,
+                    error = %e,
+                    retry = retries + 1,
+                    wait_ms = wait.as_millis() as u64,
+                    "Body read failed, retrying..."
+                );
+                tokio::time::sleep(wait).await;
+                retries += 1;
             }
         }
     }
 }
+
+async fn read_body(resp: &mut Response, throttle: &Throttle) -> Result<Vec<u8>, reqwest::Error> {
+    let mut body = Vec::new();
+    while let Some(chunk) = resp.chunk().await? {
+        throttle.download(chunk.len()).await;
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body)
+}
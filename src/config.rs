@@ -1,13 +1,55 @@
+use std::fmt;
+use std::path::PathBuf;
 use std::time::Duration;
-use clap::Parser;
+use clap::{Parser, ValueEnum};
+use regex::Regex;
+
+use crate::extractor::NofollowPolicy;
+use crate::filter::parse_pattern;
+use crate::normalize::NormalizeRule;
+use crate::retry::{Jitter, DEFAULT_RETRY_STATUSES};
+use crate::scope::ScopePolicy;
+use crate::throttle::{parse_bandwidth, parse_rps};
+use crate::visited::{parse_fp_rate, VisitedBackend};
 
 pub const DEFAULT_CONCURRENCY: usize = 50;
+pub const DEFAULT_HOST_CONCURRENCY: usize = 8;
+pub const DEFAULT_CIRCUIT_THRESHOLD: usize = 5;
+pub const DEFAULT_CIRCUIT_COOLDOWN: u64 = 30;
 pub const DEFAULT_TIMEOUT: u64 = 10;
 pub const DEFAULT_DELAY: u64 = 100;
 pub const DEFAULT_RETRY: u32 = 3;
+pub const DEFAULT_RETRY_BASE: u64 = 2000;
+pub const DEFAULT_RETRY_MAX_WAIT: u64 = 60;
+pub const DEFAULT_BLOOM_CAPACITY: usize = 10_000_000;
+pub const DEFAULT_BLOOM_FP_RATE: f64 = 0.001;
+pub const DEFAULT_ROBOTS_AGENT: &str = "sitemap-crawl";
 pub const DEFAULT_USER_AGENT: &str =
     "Mozilla/5.0 (Macintosh; Intel Mac OS X 10.6; rv:37.0) Gecko/20100101 Firefox/37.0";
 
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Sitemap mode for `.xml`/`.xml.gz` URLs, crawl mode for everything else
+    Auto,
+    /// Follow links from every input URL
+    Crawl,
+    /// Parse every input URL as a sitemap
+    Sitemap,
+    /// Find sitemaps via robots.txt and well-known paths, crawling when none exist
+    Discover,
+    /// Parse the sitemap and crawl the site, reporting orphans and pages missing from the sitemap
+    Hybrid,
+    /// Check every input URL as a sitemap against the sitemaps.org protocol instead of checking its URLs
+    Validate,
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = self.to_possible_value().expect("no skipped variants");
+        write!(f, "{}", value.get_name())
+    }
+}
+
 #[derive(Parser, Debug, Clone)]
 #[command(
     name = "sitemap-crawl",
@@ -20,10 +62,38 @@ pub struct AppConfig {
     #[arg(required = true)]
     pub urls: Vec<String>,
 
+    /// How input URLs are processed
+    #[arg(short, long, value_enum, default_value_t = Mode::Auto)]
+    pub mode: Mode,
+
     /// Max concurrent requests
     #[arg(short, long, default_value_t = DEFAULT_CONCURRENCY)]
     pub concurrency: usize,
 
+    /// Max concurrent requests to any one host
+    #[arg(long, default_value_t = DEFAULT_HOST_CONCURRENCY)]
+    pub host_concurrency: usize,
+
+    /// Minimum time between two requests to the same host in milliseconds
+    #[arg(long, default_value_t = 0)]
+    pub host_interval: u64,
+
+    /// Max requests per second across all hosts and every kind of request combined
+    #[arg(long, value_name = "RATE", value_parser = parse_rps)]
+    pub rps: Option<f64>,
+
+    /// Max download rate across all requests, in bytes per second (`500K`, `2M`, ...)
+    #[arg(long, value_name = "RATE", value_parser = parse_bandwidth)]
+    pub max_bandwidth: Option<u64>,
+
+    /// Consecutive connection errors, timeouts or 5xx responses that open a host's circuit (0 disables)
+    #[arg(long, default_value_t = DEFAULT_CIRCUIT_THRESHOLD)]
+    pub circuit_threshold: usize,
+
+    /// Seconds an open circuit fails requests before probing the host again
+    #[arg(long, default_value_t = DEFAULT_CIRCUIT_COOLDOWN)]
+    pub circuit_cooldown: u64,
+
     /// Request timeout in seconds
     #[arg(short, long, default_value_t = DEFAULT_TIMEOUT)]
     pub timeout: u64,
@@ -36,6 +106,22 @@ pub struct AppConfig {
     #[arg(short = 'r', long, default_value_t = DEFAULT_RETRY)]
     pub retry: u32,
 
+    /// First retry backoff in milliseconds, doubled on every retry
+    #[arg(long, default_value_t = DEFAULT_RETRY_BASE)]
+    pub retry_base: u64,
+
+    /// Longest wait before a retry in seconds, also capping Retry-After
+    #[arg(long, default_value_t = DEFAULT_RETRY_MAX_WAIT)]
+    pub retry_max_wait: u64,
+
+    /// How retry backoff is randomized
+    #[arg(long, value_enum, default_value_t = Jitter::Full)]
+    pub retry_jitter: Jitter,
+
+    /// Response statuses that are retried (comma-separated)
+    #[arg(long, value_name = "STATUS", value_delimiter = ',', default_values_t = DEFAULT_RETRY_STATUSES)]
+    pub retry_on: Vec<u16>,
+
     /// Custom User-Agent header
     #[arg(short, long, default_value = DEFAULT_USER_AGENT)]
     pub user_agent: String,
@@ -44,9 +130,86 @@ pub struct AppConfig {
     #[arg(short, long)]
     pub output: Option<String>,
 
+    /// Also write results to an .xlsx file next to the CSV
+    #[arg(long)]
+    pub xlsx: bool,
+
     /// Proxy server URL (e.g., http://127.0.0.1:8080)
     #[arg(short, long)]
     pub proxy: Option<String>,
+
+    /// Max click depth from the start URL to crawl (crawl mode)
+    #[arg(long)]
+    pub max_depth: Option<u32>,
+
+    /// Max number of pages to fetch per crawl (crawl mode)
+    #[arg(long)]
+    pub max_pages: Option<usize>,
+
+    /// Stop following links after this many seconds (crawl mode)
+    #[arg(long)]
+    pub max_duration: Option<u64>,
+
+    /// Which hosts a crawl may follow links into
+    #[arg(long, value_enum, default_value_t = ScopePolicy::Host)]
+    pub scope: ScopePolicy,
+
+    /// Extra host to crawl with `--scope hosts` (`*.example.com` for subdomains); repeatable
+    #[arg(long, value_name = "HOST")]
+    pub allow_host: Vec<String>,
+
+    /// Treat http:// and https:// links as different sites
+    #[arg(long)]
+    pub strict_scheme: bool,
+
+    /// Also check images, scripts, stylesheets and media hosted outside the crawl scope
+    #[arg(long)]
+    pub external_resources: bool,
+
+    /// Whether links marked nofollow (rel or meta robots) are crawled and checked
+    #[arg(long, value_enum, default_value_t = NofollowPolicy::Obey)]
+    pub nofollow: NofollowPolicy,
+
+    /// Only check URLs matching this regex (or `glob:` pattern); repeatable
+    #[arg(long, value_name = "PATTERN", value_parser = parse_pattern)]
+    pub include: Vec<Regex>,
+
+    /// Skip URLs matching this regex (or `glob:` pattern); repeatable
+    #[arg(long, value_name = "PATTERN", value_parser = parse_pattern)]
+    pub exclude: Vec<Regex>,
+
+    /// URL normalization rules applied before deduplication (comma-separated)
+    #[arg(
+        long,
+        value_enum,
+        value_delimiter = ',',
+        default_values_t = [NormalizeRule::PercentCase, NormalizeRule::StripTracking]
+    )]
+    pub normalize: Vec<NormalizeRule>,
+
+    /// Ignore robots.txt rules and Crawl-delay
+    #[arg(long)]
+    pub ignore_robots: bool,
+
+    /// Agent token used to select the robots.txt group
+    #[arg(long, default_value = DEFAULT_ROBOTS_AGENT)]
+    pub robots_agent: String,
+
+    /// Save crawl and check progress in this directory and resume from it if it already holds some
+    #[arg(long, value_name = "STATE_DIR")]
+    pub resume: Option<PathBuf>,
+
+    /// How URLs already seen are remembered
+    #[arg(long, value_enum, default_value_t = VisitedBackend::Memory)]
+    pub visited_set: VisitedBackend,
+
+    /// URLs the Bloom filter is sized for; past this, more new URLs are wrongly skipped (`--visited-set bloom`)
+    #[arg(long, value_name = "URLS", default_value_t = DEFAULT_BLOOM_CAPACITY)]
+    pub bloom_capacity: usize,
+
+    /// Share of new URLs the Bloom filter may take for seen ones at full capacity (`--visited-set bloom`)
+    #[arg(long, value_name = "RATE", value_parser = parse_fp_rate, default_value_t = DEFAULT_BLOOM_FP_RATE)]
+    pub bloom_fp_rate: f64,
 }
 
 impl AppConfig {
@@ -54,6 +217,7 @@ impl AppConfig {
         Duration::from_secs(self.timeout)
     }
 
+    #[allow(dead_code)]
     pub fn delay_duration(&self) -> Duration {
         Duration::from_millis(self.delay)
     }
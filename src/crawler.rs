@@ -1,81 +1,492 @@
-use std::collections::HashSet;
+use std::collections::{BTreeSet, HashSet};
+use std::fmt;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 
 use futures::stream::{FuturesUnordered, StreamExt};
-use tokio::sync::{Mutex, Semaphore};
-use tracing::{info, error, debug};
+use tokio::sync::Mutex;
+use tracing::{info, error, debug, warn};
 use url::Url;
 
-use crate::client::build_client;
-use crate::config::DEFAULT_CONCURRENCY;
-use crate::extractor::extract_links;
+use crate::checker::{CheckTarget, Outcome};
+use crate::client::build_status_client;
+use crate::config::AppConfig;
+use crate::extractor::{extract_links, redirect_link, Directive, NofollowPolicy, ResourceKind};
 use crate::fetcher::fetch_page;
+use crate::frontier::Frontier;
+use crate::filter::UrlFilter;
+use crate::link_graph::{LinkEdge, SharedLinkGraph};
+use crate::normalize::Normalizer;
+use crate::pipeline::{max_in_flight, TargetSink};
+use crate::retry::RetryPolicy;
+use crate::robots::RobotsCache;
+use crate::scheduler::HostScheduler;
+use crate::scope::{CrawlScope, ScopeSettings};
+use crate::state::StateStore;
+use crate::visited::{VisitedSet, VisitedSettings};
 
-type VisitedSet = Arc<Mutex<HashSet<String>>>;
+#[derive(Debug, Default)]
+pub struct Visited {
+    /// Click depth from the seed.
+    pub depth: u32,
+    /// Raw spellings that normalized to this URL.
+    pub aliases: Vec<String>,
+    pub kind: ResourceKind,
+    pub directives: BTreeSet<Directive>,
+    /// Found by following links from the start URL rather than from an extra seed.
+    pub reachable: bool,
+}
+
+impl Visited {
+    fn target(&self, url: &str) -> CheckTarget {
+        CheckTarget {
+            url: url.to_string(),
+            depth: self.reachable.then_some(self.depth),
+            aliases: self.aliases.clone(),
+            kind: self.kind,
+            directives: self.directives.iter().copied().collect(),
+            coverage: None,
+            sitemap: None,
+            fetched: None,
+        }
+    }
+}
+
+/// The pages not handed to the checker yet: those waiting to be crawled, in
+/// order, and those being crawled, with what we know about each.
+type Pending = Arc<Mutex<Frontier>>;
+
+/// Every URL a crawl has seen, with pages and assets apart, so an asset that is
+/// later linked as a page still gets crawled.
+#[derive(Clone)]
+struct Seen {
+    pages: Arc<dyn VisitedSet>,
+    assets: Arc<dyn VisitedSet>,
+}
+
+impl Seen {
+    fn new(settings: &VisitedSettings) -> Self {
+        Self {
+            pages: settings.build(),
+            assets: settings.build(),
+        }
+    }
+
+    fn contains(&self, url: &str) -> bool {
+        self.pages.contains(url) || self.assets.contains(url)
+    }
+
+    fn len(&self) -> usize {
+        self.pages.len() + self.assets.len()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CrawlLimits {
+    pub max_depth: Option<u32>,
+    pub max_pages: Option<usize>,
+    pub max_duration: Option<Duration>,
+}
+
+impl CrawlLimits {
+    pub fn from_config(config: &AppConfig) -> Self {
+        Self {
+            max_depth: config.max_depth,
+            max_pages: config.max_pages,
+            max_duration: config.max_duration.map(Duration::from_secs),
+        }
+    }
+}
+
+/// Settings shared by every crawl in a run.
+#[derive(Clone)]
+pub struct CrawlOptions {
+    pub proxy: Option<String>,
+    pub timeout: Duration,
+    pub retry: RetryPolicy,
+    pub robots: Option<Arc<RobotsCache>>,
+    pub scheduler: HostScheduler,
+    pub limits: CrawlLimits,
+    /// Pages being fetched or waiting for a host slot; the rest of the frontier waits in the queue.
+    pub max_in_flight: usize,
+    pub scope: ScopeSettings,
+    pub nofollow: NofollowPolicy,
+    pub filter: Arc<UrlFilter>,
+    pub normalizer: Arc<Normalizer>,
+    pub visited: VisitedSettings,
+    pub link_graph: SharedLinkGraph,
+    /// Where progress is saved with `--resume`.
+    pub state: Option<Arc<StateStore>>,
+}
+
+impl CrawlOptions {
+    pub fn from_config(
+        config: &AppConfig,
+        robots: Option<Arc<RobotsCache>>,
+        scheduler: HostScheduler,
+        state: Option<Arc<StateStore>>,
+    ) -> Self {
+        Self {
+            proxy: config.proxy.clone(),
+            timeout: config.timeout_duration(),
+            retry: RetryPolicy::from_config(config),
+            robots,
+            scheduler,
+            limits: CrawlLimits::from_config(config),
+            max_in_flight: max_in_flight(config.concurrency),
+            scope: ScopeSettings::from_config(config),
+            nofollow: config.nofollow,
+            filter: Arc::new(UrlFilter::from_config(config)),
+            normalizer: Arc::new(Normalizer::from_config(config)),
+            visited: VisitedSettings::from_config(config),
+            link_graph: SharedLinkGraph::default(),
+            state,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitReached {
+    MaxPages(usize),
+    MaxDuration(Duration),
+}
 
-pub async fn crawl(start_url: &str, proxy: Option<&str>, max_retries: u32) -> Vec<String> {
-    let seed = Url::parse(start_url).expect("Invalid start URL");
-    let allowed_host = seed
-        .host_str()
-        .expect("Start URL must have a host")
-        .to_string();
+impl fmt::Display for LimitReached {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LimitReached::MaxPages(n) => write!(f, "max pages ({})", n),
+            LimitReached::MaxDuration(d) => write!(f, "max duration ({}s)", d.as_secs()),
+        }
+    }
+}
 
-    info!(url = %seed, host = %allowed_host, "Starting crawl");
+pub struct CrawlResult {
+    /// Unique URLs found, pages and assets.
+    pub found: usize,
+    pub limit_reached: Option<LimitReached>,
+    /// Unique links dropped by the include/exclude filter.
+    pub excluded: usize,
+    /// Unique links not followed because they were marked nofollow.
+    pub nofollowed: usize,
+    pub scope: CrawlScope,
+}
 
-    let client = build_client(proxy).expect("Failed to build HTTP client");
-    let visited: VisitedSet = Arc::new(Mutex::new(HashSet::new()));
-    let semaphore = Arc::new(Semaphore::new(DEFAULT_CONCURRENCY));
+/// Crawl from `start_url`. Once every page reachable from it has been crawled,
+/// any `seeds` not yet seen (e.g. sitemap URLs) are crawled as well; what they
+/// lead to is reported without a click depth.
+///
+/// Found URLs go to `sink` while the crawl is still running: assets as soon as
+/// they are linked, pages once they have been fetched (or skipped), so the
+/// directives on the page itself are known.
+///
+/// With a state store, a crawl of the same `start_url` that was interrupted
+/// carries on from its saved frontier; pages it already fetched are not fetched again.
+pub async fn crawl(start_url: &str, seeds: &[String], options: &CrawlOptions, sink: &TargetSink) -> CrawlResult {
+    let raw_seed = Url::parse(start_url).expect("Invalid start URL");
+    assert!(raw_seed.host_str().is_some(), "Start URL must have a host");
+    let seed = options.normalizer.normalize(&raw_seed);
+    let scope = Arc::new(CrawlScope::new(&seed, &options.scope));
+    let limits = options.limits;
+    let obey_nofollow = options.nofollow == NofollowPolicy::Obey;
 
-    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<Url>();
+    info!(url = %seed, scope = %scope, "Starting crawl");
 
-    {
-        let mut set = visited.lock().await;
-        set.insert(seed.as_str().to_string());
+    let client = build_status_client(options.proxy.as_deref(), options.timeout).expect("Failed to build HTTP client");
+    let state = options.state.clone();
+    let saved = state.as_ref().map(|s| s.take_crawl(start_url)).unwrap_or_default();
+    let start_key: Arc<str> = Arc::from(start_url);
+    if !saved.fetched.is_empty() {
+        info!(url = %start_url, fetched = saved.fetched.len(), known = saved.visited.len(), "Resuming crawl");
     }
-    tx.send(seed).expect("channel send");
+
+    let seen = Seen::new(&options.visited);
+    let pending: Pending = Arc::default();
+    // Only counted, so it can be as compact as the seen sets.
+    let excluded = options.visited.build();
+    for url in &saved.excluded {
+        excluded.insert(url);
+    }
+    let nofollowed: Arc<Mutex<HashSet<String>>> = Arc::new(Mutex::new(saved.nofollowed));
+    let pages_crawled = Arc::new(AtomicUsize::new(saved.fetched.len()));
+    let deadline = limits.max_duration.map(|d| Instant::now() + d);
+
+    let restored: Vec<CheckTarget> = {
+        let mut pending = pending.lock().await;
+        let mut restored = Vec::new();
+        let mut frontier = Vec::new();
+
+        // What an interrupted crawl already finished with can be checked right
+        // away; every page it had not fetched yet is the frontier.
+        for (url, page) in saved.visited {
+            if page.kind != ResourceKind::Page {
+                seen.assets.insert(&url);
+                restored.push(page.target(&url));
+            } else if saved.fetched.contains(&url) {
+                seen.pages.insert(&url);
+                restored.push(page.target(&url));
+            } else {
+                seen.pages.insert(&url);
+                frontier.push((url, page));
+            }
+        }
+
+        if seen.pages.insert(seed.as_str()) {
+            let mut entry = Visited {
+                reachable: true,
+                ..Visited::default()
+            };
+            if raw_seed != seed {
+                entry.aliases.push(raw_seed.to_string());
+            }
+            if let Some(state) = &state {
+                state.record_page(start_url, seed.as_str(), &entry);
+            }
+            frontier.push((seed.to_string(), entry));
+        }
+
+        // Just the seed on a fresh crawl.
+        frontier.sort_by_key(|(_, page)| (!page.reachable, page.depth));
+        for (url, page) in frontier {
+            pending.push(url, page);
+        }
+
+        restored
+    };
+    for target in restored {
+        sink.send(target).await;
+    }
+
+    let mut pending_seeds: Vec<Url> = seeds
+        .iter()
+        .filter_map(|s| Url::parse(s).ok())
+        .map(|s| options.normalizer.normalize(&s))
+        .filter(|s| scope.contains(s) && options.filter.is_allowed(s.as_str()))
+        .collect();
 
     let mut futures = FuturesUnordered::new();
     let mut active: usize = 0;
+    let mut limit_reached = None;
 
     loop {
-        while let Ok(url) = rx.try_recv() {
+        while limit_reached.is_none() && active < options.max_in_flight {
+            if let (Some(max), Some(deadline)) = (limits.max_duration, deadline) {
+                if Instant::now() >= deadline {
+                    limit_reached = Some(LimitReached::MaxDuration(max));
+                    break;
+                }
+            }
+            if let Some(max) = limits.max_pages {
+                if pages_crawled.load(Ordering::Relaxed) + active >= max {
+                    // Only a real limit once the in-flight pages are done and links remain.
+                    if active == 0 && !pending.lock().await.is_empty() {
+                        limit_reached = Some(LimitReached::MaxPages(max));
+                    }
+                    break;
+                }
+            }
+
+            let Some((url, depth, reachable)) = pending.lock().await.pop() else {
+                break;
+            };
+
             let client = client.clone();
-            let visited = Arc::clone(&visited);
-            let sem = Arc::clone(&semaphore);
-            let tx = tx.clone();
-            let host = allowed_host.clone();
+            let pending = Arc::clone(&pending);
+            let seen = seen.clone();
+            let scheduler = options.scheduler.clone();
+            let scope = Arc::clone(&scope);
+            let robots = options.robots.clone();
+            let pages_crawled = Arc::clone(&pages_crawled);
+            let excluded = Arc::clone(&excluded);
+            let nofollowed = Arc::clone(&nofollowed);
+            let filter = Arc::clone(&options.filter);
+            let normalizer = Arc::clone(&options.normalizer);
+            let link_graph = Arc::clone(&options.link_graph);
+            let retry = options.retry.clone();
+            let state = state.clone();
+            let start_key = Arc::clone(&start_key);
+            let sink = sink.clone();
 
             active += 1;
             futures.push(tokio::spawn(async move {
-                let _permit = sem.acquire().await.expect("semaphore closed");
+                let outcome = async {
+                    if limits.max_depth.is_some_and(|max| depth >= max) {
+                        debug!(url = %url, depth, "Max depth reached, not following links");
+                        return None;
+                    }
 
-                info!(url = %url, "Crawling");
+                    // Waiting out a Crawl-delay must not hold one of the host's slots.
+                    if let Some(robots) = &robots {
+                        if !robots.is_allowed(&url).await {
+                            debug!(url = %url, "Blocked by robots.txt, not crawling");
+                            return None;
+                        }
+                        robots.wait_turn(&url).await;
+                    }
+
+                    let Ok(permit) = scheduler.acquire(url.as_str()).await else {
+                        debug!(url = %url, "Host circuit open, not crawling");
+                        return None;
+                    };
+
+                    info!(url = %url, depth, "Crawling");
+                    pages_crawled.fetch_add(1, Ordering::Relaxed);
 
-                if let Some(body) = fetch_page(&client, &url, max_retries).await {
-                    let links = extract_links(&body, &url, &host);
-                    let new_count;
+                    let (outcome, body) = fetch_page(&client, &url, &retry, &permit).await;
+                    // Free the host slot before waiting on the checker below.
+                    drop(permit);
+                    // A redirect is followed like a link, so its target is crawled in its own right.
+                    let extracted = match (body, &outcome.redirect_url) {
+                        (Some(body), _) => Some(extract_links(&body, &url, &scope, &filter)),
+                        (None, Some(location)) => Some(redirect_link(location, &url, &scope, &filter)),
+                        (None, None) => None,
+                    };
 
-                    {
-                        let mut set = visited.lock().await;
-                        let before = set.len();
-                        for link in links {
-                            let canonical = link.as_str().to_string();
-                            if set.insert(canonical) {
-                                let _ = tx.send(link);
+                    if let Some(extracted) = extracted {
+
+                        if !extracted.excluded.is_empty() {
+                            for link in extracted.excluded {
+                                if excluded.insert(link.as_str()) {
+                                    if let Some(state) = &state {
+                                        state.record_excluded(&start_key, link.as_str());
+                                    }
+                                }
                             }
                         }
-                        new_count = set.len() - before;
+
+                        let (skipped, followed): (Vec<_>, Vec<_>) = extracted
+                            .links
+                            .into_iter()
+                            .partition(|link| obey_nofollow && link.is_nofollow());
+                        if !skipped.is_empty() {
+                            let mut set = nofollowed.lock().await;
+                            for link in &skipped {
+                                let canonical = normalizer.normalize(&link.url).to_string();
+                                if let (Some(state), false) = (&state, set.contains(&canonical)) {
+                                    state.record_nofollowed(&start_key, &canonical);
+                                }
+                                set.insert(canonical);
+                            }
+                        }
+
+                        let links: Vec<(Url, Url, ResourceKind, BTreeSet<Directive>)> = {
+                            let mut graph = link_graph.lock().expect("link graph poisoned");
+                            followed
+                                .into_iter()
+                                .map(|link| {
+                                    let canonical = normalizer.normalize(&link.url);
+                                    let edge = LinkEdge {
+                                        source: url.to_string(),
+                                        anchor_text: link.anchor_text,
+                                        element: link.element,
+                                    };
+                                    if let Some(state) = &state {
+                                        state.record_link(canonical.as_str(), &edge);
+                                    }
+                                    graph.add(canonical.as_str(), edge);
+                                    (link.url, canonical, link.kind, link.directives)
+                                })
+                                .collect()
+                        };
+
+                        let mut assets = Vec::new();
+                        let mut new_count = 0;
+                        {
+                            let mut pending = pending.lock().await;
+                            if let Some(page) = pending.get_mut(url.as_str()) {
+                                let known = page.directives.len();
+                                page.directives.extend(extracted.page_directives);
+                                if let (Some(state), true) = (&state, page.directives.len() != known) {
+                                    state.record_page(&start_key, url.as_str(), page);
+                                }
+                            }
+                            for (link, canonical, kind, directives) in links {
+                                let alias = (canonical != link).then(|| link.to_string());
+
+                                // Only pages are crawled; assets are recorded so they get checked.
+                                // An asset first seen e.g. as an iframe and later linked as a page is crawled too.
+                                let new = if kind == ResourceKind::Page {
+                                    seen.pages.insert(canonical.as_str())
+                                } else {
+                                    !seen.pages.contains(canonical.as_str()) && seen.assets.insert(canonical.as_str())
+                                };
+
+                                if new {
+                                    new_count += 1;
+                                    let found = Visited {
+                                        depth: depth + 1,
+                                        aliases: alias.into_iter().collect(),
+                                        kind,
+                                        directives,
+                                        reachable,
+                                    };
+                                    if let Some(state) = &state {
+                                        state.record_page(&start_key, canonical.as_str(), &found);
+                                    }
+                                    if kind == ResourceKind::Page {
+                                        pending.push(canonical.to_string(), found);
+                                    } else {
+                                        assets.push(found.target(canonical.as_str()));
+                                    }
+                                } else if let Some(known) = pending.get_mut(canonical.as_str()) {
+                                    // Not handed to the checker yet, so what this link says still counts.
+                                    let before = (known.directives.len(), known.aliases.len());
+                                    known.directives.extend(directives);
+                                    if let Some(alias) = alias {
+                                        if !known.aliases.contains(&alias) {
+                                            known.aliases.push(alias);
+                                        }
+                                    }
+                                    let changed = before != (known.directives.len(), known.aliases.len());
+                                    if let (Some(state), true) = (&state, changed) {
+                                        state.record_page(&start_key, canonical.as_str(), known);
+                                    }
+                                } else {
+                                    // Handed over or queued on disk; joined into its row at the end.
+                                    sink.add_link(canonical.as_str(), alias, directives.into_iter().collect());
+                                }
+                            }
+                        }
+
+                        if new_count > 0 {
+                            debug!(url = %url, new_links = new_count, "Discovered new URLs");
+                        }
+                        for asset in assets {
+                            sink.send(asset).await;
+                        }
                     }
 
-                    if new_count > 0 {
-                        debug!(url = %url, new_links = new_count, "Discovered new URLs");
+                    if let Some(state) = &state {
+                        state.record_fetched(&start_key, url.as_str());
                     }
+                    Some(outcome)
                 }
+                .await;
+
+                emit(&pending, &sink, url.as_str(), outcome).await;
             }));
         }
 
         if active == 0 && futures.is_empty() {
-            break;
+            if limit_reached.is_some() || pending_seeds.is_empty() {
+                break;
+            }
+            let mut pending = pending.lock().await;
+            let mut seeded = 0;
+            for seed in pending_seeds.drain(..) {
+                if seen.pages.insert(seed.as_str()) {
+                    let page = Visited::default();
+                    if let Some(state) = &state {
+                        state.record_page(start_url, seed.as_str(), &page);
+                    }
+                    pending.push(seed.to_string(), page);
+                    seeded += 1;
+                }
+            }
+            if seeded > 0 {
+                info!(url = %start_url, seeds = seeded, "Crawling seeds not reachable from the start URL");
+            }
+            continue;
         }
 
         if let Some(result) = futures.next().await {
@@ -88,10 +499,56 @@ pub async fn crawl(start_url: &str, proxy: Option<&str>, max_retries: u32) -> Ve
         }
     }
 
-    let set = visited.lock().await;
-    let mut urls: Vec<String> = set.iter().cloned().collect();
-    urls.sort();
+    if let Some(limit) = limit_reached {
+        warn!(
+            url = %start_url,
+            limit = %limit,
+            pages = pages_crawled.load(Ordering::Relaxed),
+            "Crawl limit reached, stopping early"
+        );
+    }
+
+    // Pages left unfetched by a limit are still checked.
+    loop {
+        let Some((url, page)) = pending.lock().await.take_left() else {
+            break;
+        };
+        sink.send(page.target(&url)).await;
+    }
+    let found = seen.len();
+
+    let excluded = excluded.len();
+    // A URL linked both with and without nofollow is still checked.
+    let nofollowed = nofollowed
+        .lock()
+        .await
+        .iter()
+        .filter(|url| !seen.contains(url))
+        .count();
+
+    info!(
+        total = found,
+        pages = pages_crawled.load(Ordering::Relaxed),
+        excluded,
+        nofollowed,
+        "Crawl complete"
+    );
+    CrawlResult {
+        found,
+        limit_reached,
+        excluded,
+        nofollowed,
+        scope: CrawlScope::clone(&scope),
+    }
+}
 
-    info!(total = urls.len(), "Crawl complete");
-    urls
+/// Hand `url` to the checker with everything the crawl learned about it, unless
+/// it already was. With the crawler's own `outcome`, it is not requested again.
+async fn emit(pending: &Pending, sink: &TargetSink, url: &str, outcome: Option<Outcome>) {
+    let Some(page) = pending.lock().await.remove(url) else {
+        return;
+    };
+    let mut target = page.target(url);
+    target.fetched = outcome;
+    sink.send(target).await;
 }
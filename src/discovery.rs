@@ -0,0 +1,111 @@
+use std::fmt;
+
+use reqwest::Client;
+use tracing::{debug, info};
+use url::Url;
+
+use crate::client::{get_with_retry, Pacing};
+use crate::retry::RetryPolicy;
+use crate::robots::{fetch_robots, RobotsCache};
+use crate::throttle::Throttle;
+
+/// Paths probed when robots.txt does not list every sitemap.
+const WELL_KNOWN_SITEMAPS: &[&str] = &["/sitemap.xml", "/sitemap_index.xml", "/sitemap.xml.gz"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SitemapSource {
+    RobotsTxt,
+    WellKnown,
+}
+
+impl fmt::Display for SitemapSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SitemapSource::RobotsTxt => write!(f, "robots.txt"),
+            SitemapSource::WellKnown => write!(f, "well-known location"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DiscoveredSitemap {
+    pub url: String,
+    pub source: SitemapSource,
+}
+
+/// Find sitemaps for the site of `site_url` from robots.txt `Sitemap:` lines
+/// and the usual well-known locations.
+pub async fn discover_sitemaps(
+    site_url: &str,
+    client: &Client,
+    robots: Option<&RobotsCache>,
+    robots_agent: &str,
+    retry: &RetryPolicy,
+    throttle: &Throttle,
+) -> Vec<DiscoveredSitemap> {
+    let Ok(site) = Url::parse(site_url) else {
+        return Vec::new();
+    };
+    let origin = site.origin().ascii_serialization();
+
+    let rules = match robots {
+        Some(cache) => cache.rules(&site).await,
+        None => fetch_robots(client, &origin, robots_agent, retry, throttle).await,
+    };
+
+    let mut found: Vec<DiscoveredSitemap> = Vec::new();
+
+    for sitemap in rules.sitemaps() {
+        if !found.iter().any(|d| &d.url == sitemap) {
+            found.push(DiscoveredSitemap {
+                url: sitemap.clone(),
+                source: SitemapSource::RobotsTxt,
+            });
+        }
+    }
+
+    for path in WELL_KNOWN_SITEMAPS {
+        let candidate = format!("{}{}", origin, path);
+        if found.iter().any(|d| d.url == candidate) {
+            continue;
+        }
+        if probe_sitemap(client, &candidate, retry, throttle).await {
+            found.push(DiscoveredSitemap {
+                url: candidate,
+                source: SitemapSource::WellKnown,
+            });
+        }
+    }
+
+    for sitemap in &found {
+        info!(url = %sitemap.url, source = %sitemap.source, "Discovered sitemap");
+    }
+
+    found
+}
+
+/// A probe only counts when the server answers with something XML or gzip-like,
+/// so sites that serve a 200 HTML page for every path are not mistaken for sitemaps.
+async fn probe_sitemap(client: &Client, url: &str, retry: &RetryPolicy, throttle: &Throttle) -> bool {
+    match get_with_retry(client, url, retry, Pacing::Throttle(throttle)).await.result {
+        Ok(resp) => {
+            if !resp.status().is_success() {
+                debug!(url = %url, status = %resp.status(), "No sitemap at well-known location");
+                return false;
+            }
+            let content_type = resp
+                .headers()
+                .get(reqwest::header::CONTENT_TYPE)
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or_default()
+                .to_lowercase();
+            content_type.contains("xml")
+                || content_type.contains("gzip")
+                || content_type.contains("octet-stream")
+        }
+        Err(e) => {
+            debug!(url = %url, error = %e, "Sitemap probe failed");
+            false
+        }
+    }
+}
@@ -1,11 +1,238 @@
 use rust_xlsxwriter::{Color, Format, FormatAlign, FormatBorder, Workbook, XlsxError};
 use std::fs::File;
-use std::io::{BufWriter, Write};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use tracing::warn;
 
 use crate::checker::UrlStatus;
+use crate::link_graph::LinkEdge;
+use crate::pipeline::Merged;
+use crate::sitemap_parser::SitemapEntry;
+
+/// How a column's cells are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Style {
+    /// Free text: quoted in the CSV, left-aligned in the XLSX.
+    Text,
+    /// A short value, centred.
+    Label,
+    /// A number, centred; anything that does not parse is written as text.
+    Number,
+    /// The HTTP status, a bold number (or `ERR`).
+    Status,
+}
+
+/// Column headers, widths and styles shared by the CSV and XLSX outputs.
+const COLUMNS: &[(&str, f64, Style)] = &[
+    ("No.", 8.0, Style::Number),
+    ("URL", 80.0, Style::Text),
+    ("Type", 12.0, Style::Label),
+    ("Status", 12.0, Style::Status),
+    ("Status Text", 22.0, Style::Text),
+    ("Redirect URL", 60.0, Style::Text),
+    ("Depth", 8.0, Style::Number),
+    ("Aliases", 60.0, Style::Text),
+    ("Found On", 80.0, Style::Text),
+    ("Anchor Text", 40.0, Style::Text),
+    ("Directives", 24.0, Style::Text),
+    ("Coverage", 16.0, Style::Label),
+    ("Retries", 8.0, Style::Number),
+    ("Response Time (ms)", 12.0, Style::Number),
+    ("Content Type", 28.0, Style::Text),
+    ("Last Modified", 26.0, Style::Label),
+    ("Change Freq", 12.0, Style::Label),
+    ("Priority", 8.0, Style::Number),
+    ("Sitemap", 60.0, Style::Text),
+];
+
+const NO: usize = 0;
+const URL: usize = 1;
+const DEPTH: usize = 6;
+const ALIASES: usize = 7;
+const FOUND_ON: usize = 8;
+const ANCHOR_TEXT: usize = 9;
+const DIRECTIVES: usize = 10;
+const LAST_MODIFIED: usize = 15;
+
+/// Multi-value cells are joined with this separator.
+const LIST_SEPARATOR: &str = " | ";
+
+fn found_on(inlinks: &[LinkEdge]) -> String {
+    inlinks
+        .iter()
+        .map(|e| e.source.as_str())
+        .collect::<Vec<_>>()
+        .join(LIST_SEPARATOR)
+}
+
+fn directives(result: &UrlStatus) -> String {
+    result
+        .directives
+        .iter()
+        .map(|d| d.to_string())
+        .collect::<Vec<_>>()
+        .join(LIST_SEPARATOR)
+}
+
+/// The sitemap's `<lastmod>`, shown as a plain date when it had no time of day.
+fn last_modified(sitemap: &SitemapEntry) -> String {
+    let Some(lastmod) = sitemap.lastmod else {
+        return String::new();
+    };
+    if lastmod.offset().local_minus_utc() == 0 && lastmod.time() == chrono::NaiveTime::MIN {
+        lastmod.format("%Y-%m-%d").to_string()
+    } else {
+        lastmod.to_rfc3339()
+    }
+}
+
+fn anchor_texts(inlinks: &[LinkEdge]) -> String {
+    inlinks
+        .iter()
+        .map(|e| e.anchor_text.as_str())
+        .collect::<Vec<_>>()
+        .join(LIST_SEPARATOR)
+}
+
+/// The `Last Modified`, `Change Freq`, `Priority` and `Sitemap` cells.
+fn sitemap_cells(sitemap: Option<&SitemapEntry>) -> [String; 4] {
+    [
+        sitemap.map(last_modified).unwrap_or_default(),
+        sitemap.and_then(|s| s.changefreq).map(|f| f.to_string()).unwrap_or_default(),
+        sitemap.and_then(|s| s.priority).map(|p| p.to_string()).unwrap_or_default(),
+        sitemap.map(|s| s.sitemap.clone()).unwrap_or_default(),
+    ]
+}
+
+/// One result as the text of its cells, in `COLUMNS` order.
+#[derive(Debug, Clone)]
+pub struct Row {
+    cells: Vec<String>,
+}
+
+impl Row {
+    pub fn new(result: &UrlStatus, inlinks: &[LinkEdge]) -> Self {
+        let mut cells = vec![
+            String::new(),
+            result.url.clone(),
+            result.kind.to_string(),
+            result.status_code.map(|c| c.to_string()).unwrap_or_else(|| "ERR".to_string()),
+            result.status_text.clone(),
+            result.redirect_url.clone().unwrap_or_default(),
+            result.depth.map(|d| d.to_string()).unwrap_or_default(),
+            result.aliases.join(LIST_SEPARATOR),
+            found_on(inlinks),
+            anchor_texts(inlinks),
+            directives(result),
+            result.coverage.map(|c| c.to_string()).unwrap_or_default(),
+            result.retries.to_string(),
+            result.response_time.map(|t| t.as_millis().to_string()).unwrap_or_default(),
+            result.content_type.clone().unwrap_or_default(),
+        ];
+        cells.extend(sitemap_cells(result.sitemap.as_ref()));
+        Self { cells }
+    }
+
+    pub fn url(&self) -> &str {
+        &self.cells[URL]
+    }
+
+    /// Replace the `Found On` and `Anchor Text` cells.
+    pub fn set_inlinks(&mut self, inlinks: &[LinkEdge]) {
+        self.cells[FOUND_ON] = found_on(inlinks);
+        self.cells[ANCHOR_TEXT] = anchor_texts(inlinks);
+    }
+
+    /// Add what duplicates of this URL said about it: their aliases and
+    /// directives, a shallower depth, and sitemap fields this row is missing.
+    /// Returns how many aliases were new.
+    pub fn merge(&mut self, merged: Merged) -> usize {
+        let mut aliases: Vec<String> = self.cells[ALIASES]
+            .split(LIST_SEPARATOR)
+            .filter(|a| !a.is_empty())
+            .map(String::from)
+            .collect();
+        let before = aliases.len();
+        for alias in merged.aliases {
+            if alias != self.cells[URL] && !aliases.contains(&alias) {
+                aliases.push(alias);
+            }
+        }
+        let added = aliases.len() - before;
+        self.cells[ALIASES] = aliases.join(LIST_SEPARATOR);
+
+        let mut directives: Vec<String> = self.cells[DIRECTIVES]
+            .split(LIST_SEPARATOR)
+            .filter(|d| !d.is_empty())
+            .map(String::from)
+            .collect();
+        for directive in merged.directives {
+            let directive = directive.to_string();
+            if !directives.contains(&directive) {
+                directives.push(directive);
+            }
+        }
+        self.cells[DIRECTIVES] = directives.join(LIST_SEPARATOR);
+
+        if let Some(depth) = merged.depth {
+            if self.cells[DEPTH].parse::<u32>().map_or(true, |known| depth < known) {
+                self.cells[DEPTH] = depth.to_string();
+            }
+        }
+        let sitemap = sitemap_cells(merged.sitemap.as_ref());
+        for (cell, merged) in self.cells[LAST_MODIFIED..].iter_mut().zip(sitemap) {
+            if cell.is_empty() {
+                *cell = merged;
+            }
+        }
+        added
+    }
+}
+
+/// Quote a CSV cell, doubling the quotes inside it.
+fn quote(cell: &str) -> String {
+    format!("\"{}\"", cell.replace('"', "\"\""))
+}
+
+/// Read one CSV record, which may span lines inside a quoted cell; `None` at
+/// the end of the file.
+fn read_record(reader: &mut impl BufRead) -> std::io::Result<Option<Vec<String>>> {
+    let mut cells = Vec::new();
+    let mut cell = String::new();
+    let mut quoted = false;
+    let mut line = String::new();
+
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            if quoted || !cell.is_empty() || !cells.is_empty() {
+                cells.push(cell);
+                return Ok(Some(cells));
+            }
+            return Ok(None);
+        }
+        let mut chars = line.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' if quoted && chars.peek() == Some(&'"') => {
+                    chars.next();
+                    cell.push('"');
+                }
+                '"' => quoted = !quoted,
+                ',' if !quoted => cells.push(std::mem::take(&mut cell)),
+                '\n' | '\r' if !quoted => {}
+                c => cell.push(c),
+            }
+        }
+        if !quoted {
+            cells.push(cell);
+            return Ok(Some(cells));
+        }
+    }
+}
 
 pub struct CsvWriter {
     writer: BufWriter<File>,
+    path: String,
     row_count: u32,
 }
 
@@ -13,31 +240,17 @@ impl CsvWriter {
     pub fn new(path: &str) -> std::io::Result<Self> {
         let file = File::create(path)?;
         let mut writer = BufWriter::new(file);
-        writeln!(writer, "No,URL,Status,Status Text,Redirect URL")?;
+        write_header(&mut writer)?;
         writer.flush()?;
 
-        Ok(Self { writer, row_count: 0 })
+        Ok(Self { writer, path: path.to_string(), row_count: 0 })
     }
 
-    pub fn append_row(&mut self, result: &UrlStatus) -> std::io::Result<()> {
+    pub fn append_row(&mut self, result: &UrlStatus, inlinks: &[LinkEdge]) -> std::io::Result<()> {
         self.row_count += 1;
-
-        let status = match result.status_code {
-            Some(code) => code.to_string(),
-            None => "ERR".to_string(),
-        };
-
-        let redirect = result.redirect_url.as_deref().unwrap_or("");
-
-        writeln!(
-            self.writer,
-            "{},\"{}\",{},\"{}\",\"{}\"",
-            self.row_count,
-            result.url.replace('"', "\"\""),
-            status,
-            result.status_text.replace('"', "\"\""),
-            redirect.replace('"', "\"\""),
-        )?;
+        let mut row = Row::new(result, inlinks);
+        row.cells[NO] = self.row_count.to_string();
+        write_row(&mut self.writer, &row)?;
         self.writer.flush()?;
 
         Ok(())
@@ -46,69 +259,241 @@ impl CsvWriter {
     pub fn row_count(&self) -> u32 {
         self.row_count
     }
+
+    /// Rewrite the file once the run is over, passing every row through
+    /// `complete` to fill in what was not known yet when it was written, and
+    /// hand the final rows to `xlsx`.
+    pub fn finish(self, mut complete: impl FnMut(&mut Row), mut xlsx: Option<&mut XlsxWriter>) -> std::io::Result<String> {
+        drop(self.writer);
+        let tmp_path = format!("{}.tmp", self.path);
+        let mut reader = BufReader::new(File::open(&self.path)?);
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+
+        // The header is written afresh.
+        read_record(&mut reader)?;
+        write_header(&mut writer)?;
+        while let Some(cells) = read_record(&mut reader)? {
+            if cells.len() != COLUMNS.len() {
+                continue;
+            }
+            let mut row = Row { cells };
+            complete(&mut row);
+            write_row(&mut writer, &row)?;
+            if let Some(writer) = xlsx.as_deref_mut() {
+                if let Err(e) = writer.append_row(&row) {
+                    warn!(row = %row.cells[NO], error = %e, "Failed to write XLSX row, leaving the rest out of the workbook");
+                    xlsx = None;
+                }
+            }
+        }
+        writer.flush()?;
+        drop(writer);
+
+        std::fs::rename(&tmp_path, &self.path)?;
+        Ok(self.path)
+    }
+}
+
+fn write_header(writer: &mut impl Write) -> std::io::Result<()> {
+    let header: Vec<&str> = COLUMNS
+        .iter()
+        .map(|(name, _, _)| name.trim_end_matches('.'))
+        .collect();
+    writeln!(writer, "{}", header.join(","))
+}
+
+fn write_row(writer: &mut impl Write, row: &Row) -> std::io::Result<()> {
+    let cells: Vec<String> = row
+        .cells
+        .iter()
+        .zip(COLUMNS)
+        .map(|(cell, (_, _, style))| match style {
+            Style::Text => quote(cell),
+            _ => cell.clone(),
+        })
+        .collect();
+    writeln!(writer, "{}", cells.join(","))
+}
+
+/// Longest text Excel keeps in one cell.
+const XLSX_MAX_CHARS: usize = 32_767;
+
+/// Rows per worksheet, the header included; further rows go on a new sheet.
+const XLSX_MAX_ROWS: u32 = 1_048_576;
+
+/// `cell`, cut to fit an XLSX cell and marked with `…` when it was cut.
+fn fit_cell(cell: &str) -> std::borrow::Cow<'_, str> {
+    match cell.char_indices().nth(XLSX_MAX_CHARS) {
+        Some(_) => {
+            let (end, _) = cell.char_indices().nth(XLSX_MAX_CHARS - 1).expect("longer than the limit");
+            format!("{}…", &cell[..end]).into()
+        }
+        None => cell.into(),
+    }
 }
 
-pub fn export_to_xlsx(results: &[UrlStatus], path: &str) -> Result<(), XlsxError> {
-    let mut workbook = Workbook::new();
-    let worksheet = workbook.add_worksheet();
-
-    worksheet.set_name("Sitemap")?;
-    worksheet.set_column_width(0, 8)?;
-    worksheet.set_column_width(1, 80)?;
-    worksheet.set_column_width(2, 12)?;
-    worksheet.set_column_width(3, 22)?;
-    worksheet.set_column_width(4, 60)?;
-
-    let header_format = Format::new()
-        .set_bold()
-        .set_font_size(12)
-        .set_font_color(Color::White)
-        .set_background_color(Color::RGB(0x2E86AB))
-        .set_align(FormatAlign::Center)
-        .set_border(FormatBorder::Thin);
-
-    let idx_fmt = Format::new()
-        .set_font_size(11)
-        .set_align(FormatAlign::Center)
-        .set_border(FormatBorder::Thin);
-
-    let cell_fmt = Format::new()
-        .set_font_size(11)
-        .set_border(FormatBorder::Thin);
-
-    let code_fmt = Format::new()
-        .set_font_size(11)
-        .set_bold()
-        .set_align(FormatAlign::Center)
-        .set_border(FormatBorder::Thin);
-
-    worksheet.write_string_with_format(0, 0, "No.", &header_format)?;
-    worksheet.write_string_with_format(0, 1, "URL", &header_format)?;
-    worksheet.write_string_with_format(0, 2, "Status", &header_format)?;
-    worksheet.write_string_with_format(0, 3, "Status Text", &header_format)?;
-    worksheet.write_string_with_format(0, 4, "Redirect URL", &header_format)?;
-
-    for (i, result) in results.iter().enumerate() {
-        let row = (i + 1) as u32;
-
-        worksheet.write_number_with_format(row, 0, (i + 1) as f64, &idx_fmt)?;
-        worksheet.write_string_with_format(row, 1, &result.url, &cell_fmt)?;
-
-        match result.status_code {
-            Some(code) => {
-                worksheet.write_number_with_format(row, 2, code as f64, &code_fmt)?;
+/// Writes final rows into an XLSX workbook; the file is saved by `finish`.
+pub struct XlsxWriter {
+    workbook: Workbook,
+    path: String,
+    /// Index of the worksheet being filled and the rows on it so far.
+    sheet: usize,
+    sheet_rows: u32,
+    header_fmt: Format,
+    idx_fmt: Format,
+    cell_fmt: Format,
+    code_fmt: Format,
+}
+
+impl XlsxWriter {
+    pub fn new(path: &str) -> Result<Self, XlsxError> {
+        let header_fmt = Format::new()
+            .set_bold()
+            .set_font_size(12)
+            .set_font_color(Color::White)
+            .set_background_color(Color::RGB(0x2E86AB))
+            .set_align(FormatAlign::Center)
+            .set_border(FormatBorder::Thin);
+
+        let idx_fmt = Format::new()
+            .set_font_size(11)
+            .set_align(FormatAlign::Center)
+            .set_border(FormatBorder::Thin);
+
+        let cell_fmt = Format::new()
+            .set_font_size(11)
+            .set_border(FormatBorder::Thin);
+
+        let code_fmt = Format::new()
+            .set_font_size(11)
+            .set_bold()
+            .set_align(FormatAlign::Center)
+            .set_border(FormatBorder::Thin);
+
+        let mut writer = Self {
+            workbook: Workbook::new(),
+            path: path.to_string(),
+            sheet: 0,
+            sheet_rows: 0,
+            header_fmt,
+            idx_fmt,
+            cell_fmt,
+            code_fmt,
+        };
+        writer.add_sheet("Sitemap")?;
+        Ok(writer)
+    }
+
+    /// Start a worksheet with the header row and make it the one being filled.
+    fn add_sheet(&mut self, name: &str) -> Result<(), XlsxError> {
+        // Rows are only ever appended, so each is written out to a temporary
+        // file as soon as the next one starts instead of being kept.
+        let worksheet = self.workbook.add_worksheet_with_constant_memory();
+        worksheet.set_name(name)?;
+        for (col, (name, width, _)) in COLUMNS.iter().enumerate() {
+            worksheet.set_column_width(col as u16, *width)?;
+            worksheet.write_string_with_format(0, col as u16, *name, &self.header_fmt)?;
+        }
+        self.sheet = self.workbook.worksheets().len() - 1;
+        self.sheet_rows = 1;
+        Ok(())
+    }
+
+    pub fn append_row(&mut self, row: &Row) -> Result<(), XlsxError> {
+        if self.sheet_rows == XLSX_MAX_ROWS {
+            let name = format!("Sitemap ({})", self.sheet + 2);
+            warn!(sheet = %name, "XLSX worksheet is full, continuing on a new one");
+            self.add_sheet(&name)?;
+        }
+        let index = self.sheet_rows;
+        self.sheet_rows += 1;
+        let worksheet = self.workbook.worksheet_from_index(self.sheet)?;
+
+        for (col, (cell, (_, _, style))) in row.cells.iter().zip(COLUMNS).enumerate() {
+            let col = col as u16;
+            let format = match style {
+                Style::Text => &self.cell_fmt,
+                Style::Label | Style::Number => &self.idx_fmt,
+                Style::Status => &self.code_fmt,
+            };
+            // Empty short cells are left blank; text cells keep their border.
+            if cell.is_empty() && *style != Style::Text {
+                continue;
             }
-            None => {
-                worksheet.write_string_with_format(row, 2, "ERR", &code_fmt)?;
+            match cell.parse::<f64>() {
+                Ok(number) if matches!(style, Style::Number | Style::Status) => {
+                    worksheet.write_number_with_format(index, col, number, format)?;
+                }
+                _ => {
+                    worksheet.write_string_with_format(index, col, fit_cell(cell), format)?;
+                }
             }
         }
 
-        worksheet.write_string_with_format(row, 3, &result.status_text, &cell_fmt)?;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<String, XlsxError> {
+        self.workbook.save(&self.path)?;
+        Ok(self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        let redirect = result.redirect_url.as_deref().unwrap_or("");
-        worksheet.write_string_with_format(row, 4, redirect, &cell_fmt)?;
+    #[test]
+    fn read_record_splits_quoted_cells() {
+        let csv = "1,\"a, \"\"b\"\"\",,\"\"\n2,\"multi\nline\",x,\n";
+        let mut reader = csv.as_bytes();
+        assert_eq!(read_record(&mut reader).unwrap().unwrap(), ["1", "a, \"b\"", "", ""]);
+        assert_eq!(read_record(&mut reader).unwrap().unwrap(), ["2", "multi\nline", "x", ""]);
+        assert!(read_record(&mut reader).unwrap().is_none());
     }
 
-    workbook.save(path)?;
-    Ok(())
+    #[test]
+    fn written_rows_read_back_unchanged() {
+        let cells: Vec<String> = COLUMNS
+            .iter()
+            .map(|(_, _, style)| match style {
+                Style::Text => "a \"quoted\", listed | value".to_string(),
+                _ => "7".to_string(),
+            })
+            .collect();
+        let mut written = Vec::new();
+        write_row(&mut written, &Row { cells: cells.clone() }).unwrap();
+        assert_eq!(read_record(&mut written.as_slice()).unwrap().unwrap(), cells);
+    }
+
+    #[test]
+    fn merge_adds_new_aliases_and_shallower_depth() {
+        let mut cells = vec![String::new(); COLUMNS.len()];
+        cells[URL] = "https://example.com/a".to_string();
+        cells[DEPTH] = "3".to_string();
+        cells[ALIASES] = "https://example.com/a?utm_source=x".to_string();
+        let mut row = Row { cells };
+        let merged = Merged {
+            aliases: vec![
+                "https://example.com/a?utm_source=x".to_string(),
+                "https://example.com/a?gclid=1".to_string(),
+            ],
+            directives: Vec::new(),
+            depth: Some(1),
+            sitemap: None,
+        };
+        assert_eq!(row.merge(merged), 1);
+        assert_eq!(row.cells[ALIASES], "https://example.com/a?utm_source=x | https://example.com/a?gclid=1");
+        assert_eq!(row.cells[DEPTH], "1");
+    }
+
+    #[test]
+    fn fit_cell_cuts_only_overlong_text() {
+        assert_eq!(fit_cell("short"), "short");
+        let exact = "é".repeat(XLSX_MAX_CHARS);
+        assert_eq!(fit_cell(&exact), exact);
+        let cut = fit_cell(&"é".repeat(XLSX_MAX_CHARS + 10)).into_owned();
+        assert_eq!(cut.chars().count(), XLSX_MAX_CHARS);
+        assert!(cut.ends_with('…'));
+    }
 }
@@ -1,26 +1,481 @@
-use scraper::{Html, Selector};
+use std::collections::BTreeSet;
+use std::fmt;
+use std::sync::LazyLock;
+
+use clap::ValueEnum;
+use regex::Regex;
+use scraper::{ElementRef, Html, Selector};
 use url::Url;
 
-pub fn extract_links(body: &str, base: &Url, allowed_host: &str) -> Vec<Url> {
+use crate::filter::UrlFilter;
+use crate::scope::CrawlScope;
+
+/// Every element that can reference another URL.
+const LINK_SELECTOR: &str = "a[href], img[src], img[srcset], source[src], source[srcset], \
+    link[href], script[src], iframe[src], video[src], video[poster], audio[src], \
+    [style], style";
+
+/// Every element name `extract_links` and `redirect_link` record as a link's source element.
+pub const LINK_ELEMENTS: &[&str] = &[
+    "a", "img", "source", "link", "script", "iframe", "video", "audio", "style", "meta", "redirect",
+];
+
+static LINKS: LazyLock<Selector> = LazyLock::new(|| Selector::parse(LINK_SELECTOR).expect("valid CSS selector"));
+static BASE_HREF: LazyLock<Selector> = LazyLock::new(|| Selector::parse("base[href]").expect("valid CSS selector"));
+static META_CONTENT: LazyLock<Selector> = LazyLock::new(|| Selector::parse("meta[content]").expect("valid CSS selector"));
+static IMG_ALT: LazyLock<Selector> = LazyLock::new(|| Selector::parse("img[alt]").expect("valid CSS selector"));
+
+static CSS_URL: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"url\(\s*['"]?([^'")]+?)['"]?\s*\)"#).expect("valid regex")
+});
+
+/// What a link points at. Only pages are crawled further; everything else is just checked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum ResourceKind {
+    #[default]
+    Page,
+    Image,
+    Script,
+    Stylesheet,
+    Font,
+    Frame,
+    Media,
+    Other,
+}
+
+impl ResourceKind {
+    pub const ALL: [ResourceKind; 8] = [
+        ResourceKind::Page,
+        ResourceKind::Image,
+        ResourceKind::Script,
+        ResourceKind::Stylesheet,
+        ResourceKind::Font,
+        ResourceKind::Frame,
+        ResourceKind::Media,
+        ResourceKind::Other,
+    ];
+
+    /// Inverse of `Display`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.to_string() == name)
+    }
+}
+
+impl fmt::Display for ResourceKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ResourceKind::Page => "page",
+            ResourceKind::Image => "image",
+            ResourceKind::Script => "script",
+            ResourceKind::Stylesheet => "stylesheet",
+            ResourceKind::Font => "font",
+            ResourceKind::Frame => "frame",
+            ResourceKind::Media => "media",
+            ResourceKind::Other => "other",
+        };
+        f.pad(name)
+    }
+}
+
+/// Crawler directives that applied to a URL, either on the link that led to it
+/// or declared by the page itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Directive {
+    /// Linked with `rel="nofollow"`, or from a page whose meta robots say `nofollow`
+    Nofollow,
+    /// Target of a `<meta http-equiv="refresh">`
+    MetaRefresh,
+    /// Resolved against a `<base href>` rather than the page URL
+    BaseHref,
+    /// The page's meta robots say `noindex`
+    Noindex,
+    /// The page's meta robots say `nofollow`
+    RobotsNofollow,
+}
+
+impl Directive {
+    pub const ALL: [Directive; 5] = [
+        Directive::Nofollow,
+        Directive::MetaRefresh,
+        Directive::BaseHref,
+        Directive::Noindex,
+        Directive::RobotsNofollow,
+    ];
+
+    /// Inverse of `Display`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|directive| directive.to_string() == name)
+    }
+}
+
+impl fmt::Display for Directive {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Directive::Nofollow => "nofollow",
+            Directive::MetaRefresh => "meta-refresh",
+            Directive::BaseHref => "base-href",
+            Directive::Noindex => "noindex",
+            Directive::RobotsNofollow => "robots-nofollow",
+        };
+        f.pad(name)
+    }
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NofollowPolicy {
+    /// Do not crawl or check links marked nofollow
+    Obey,
+    /// Treat nofollow links like any other link (they are still annotated)
+    Ignore,
+}
+
+impl fmt::Display for NofollowPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = self.to_possible_value().expect("no skipped variants");
+        write!(f, "{}", value.get_name())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ExtractedLink {
+    pub url: Url,
+    pub anchor_text: String,
+    pub element: &'static str,
+    pub kind: ResourceKind,
+    pub directives: BTreeSet<Directive>,
+}
+
+impl ExtractedLink {
+    pub fn is_nofollow(&self) -> bool {
+        self.directives.contains(&Directive::Nofollow)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ExtractedLinks {
+    pub links: Vec<ExtractedLink>,
+    /// In-scope links dropped by the include/exclude filter.
+    pub excluded: Vec<Url>,
+    /// Directives the page declares about itself via meta robots.
+    pub page_directives: BTreeSet<Directive>,
+}
+
+/// Resolves, scopes and filters candidate URLs as the document is walked.
+struct LinkCollector<'a> {
+    page_url: &'a Url,
+    /// `<base href>` if the page has one, otherwise the page URL.
+    base: Url,
+    scope: &'a CrawlScope,
+    filter: &'a UrlFilter,
+    extracted: ExtractedLinks,
+}
+
+impl LinkCollector<'_> {
+    fn push(&mut self, href: &str, element: &'static str, kind: ResourceKind, anchor_text: String) {
+        self.push_with(href, element, kind, anchor_text, BTreeSet::new());
+    }
+
+    /// `rel` holds the link's own `rel` attribute, if it has one.
+    fn push_link(&mut self, href: &str, element: &'static str, kind: ResourceKind, anchor_text: String, rel: &str) {
+        let mut directives = BTreeSet::new();
+        let page_nofollow = self.extracted.page_directives.contains(&Directive::RobotsNofollow);
+        if kind == ResourceKind::Page && (page_nofollow || has_token(rel, "nofollow")) {
+            directives.insert(Directive::Nofollow);
+        }
+        self.push_with(href, element, kind, anchor_text, directives);
+    }
+
+    fn push_with(
+        &mut self,
+        href: &str,
+        element: &'static str,
+        kind: ResourceKind,
+        anchor_text: String,
+        mut directives: BTreeSet<Directive>,
+    ) {
+        let Some(mut url) = resolve_url(href, &self.base) else {
+            return;
+        };
+        if self.base != *self.page_url && resolve_url(href, self.page_url).as_ref() != Some(&url) {
+            directives.insert(Directive::BaseHref);
+        }
+        let in_scope = match kind {
+            ResourceKind::Page => self.scope.contains(&url),
+            _ => self.scope.contains_resource(&url),
+        };
+        if !in_scope {
+            return;
+        }
+        url.set_fragment(None);
+
+        if !self.filter.is_allowed(url.as_str()) {
+            self.extracted.excluded.push(url);
+            return;
+        }
+
+        self.extracted.links.push(ExtractedLink {
+            url,
+            anchor_text,
+            element,
+            kind,
+            directives,
+        });
+    }
+}
+
+pub fn extract_links(body: &str, page_url: &Url, scope: &CrawlScope, filter: &UrlFilter) -> ExtractedLinks {
     let document = Html::parse_document(body);
-    let selector = Selector::parse("a[href]").expect("valid CSS selector");
-
-    document
-        .select(&selector)
-        .filter_map(|el| el.value().attr("href"))
-        .filter_map(|href| resolve_url(href, base))
-        .filter(|url| is_same_domain(url, allowed_host))
-        .map(|mut url| {
-            url.set_fragment(None);
-            url
-        })
+
+    // Only the first <base href> counts, and relative ones resolve against the page.
+    let base = document
+        .select(&BASE_HREF)
+        .next()
+        .and_then(|el| el.value().attr("href"))
+        .and_then(|href| page_url.join(href.trim()).ok());
+
+    let mut links = LinkCollector {
+        page_url,
+        base: base.unwrap_or_else(|| page_url.clone()),
+        scope,
+        filter,
+        extracted: ExtractedLinks::default(),
+    };
+
+    for el in document.select(&META_CONTENT) {
+        let value = el.value();
+        let content = value.attr("content").unwrap_or_default();
+
+        let name = value.attr("name").unwrap_or_default();
+        if name.eq_ignore_ascii_case("robots") {
+            links.extracted.page_directives.extend(robots_directives(content));
+        }
+
+        let http_equiv = value.attr("http-equiv").unwrap_or_default();
+        if http_equiv.eq_ignore_ascii_case("refresh") {
+            if let Some(target) = refresh_target(content) {
+                let directives = BTreeSet::from([Directive::MetaRefresh]);
+                links.push_with(target, "meta", ResourceKind::Page, String::new(), directives);
+            }
+        }
+    }
+
+    for el in document.select(&LINKS) {
+        let value = el.value();
+
+        if let Some(style) = value.attr("style") {
+            for href in css_urls(style) {
+                links.push(href, "style", css_kind(href), String::new());
+            }
+        }
+
+        match value.name() {
+            "a" => {
+                if let Some(href) = value.attr("href") {
+                    let rel = value.attr("rel").unwrap_or_default();
+                    links.push_link(href, "a", ResourceKind::Page, anchor_text(&el), rel);
+                }
+            }
+            "img" => {
+                let alt = value.attr("alt").unwrap_or_default().trim().to_string();
+                if let Some(src) = value.attr("src") {
+                    links.push(src, "img", ResourceKind::Image, alt.clone());
+                }
+                for src in value.attr("srcset").map(srcset_urls).unwrap_or_default() {
+                    links.push(src, "img", ResourceKind::Image, alt.clone());
+                }
+            }
+            "source" => {
+                let in_media = el
+                    .parent()
+                    .and_then(ElementRef::wrap)
+                    .is_some_and(|p| matches!(p.value().name(), "video" | "audio"));
+                let kind = if in_media { ResourceKind::Media } else { ResourceKind::Image };
+                if let Some(src) = value.attr("src") {
+                    links.push(src, "source", kind, String::new());
+                }
+                for src in value.attr("srcset").map(srcset_urls).unwrap_or_default() {
+                    links.push(src, "source", kind, String::new());
+                }
+            }
+            "link" => {
+                let rel = value.attr("rel").unwrap_or_default().to_lowercase();
+                if let (Some(href), Some(kind)) = (value.attr("href"), link_kind(&rel, value.attr("as"))) {
+                    links.push_link(href, "link", kind, String::new(), &rel);
+                }
+            }
+            "script" => {
+                if let Some(src) = value.attr("src") {
+                    links.push(src, "script", ResourceKind::Script, String::new());
+                }
+            }
+            "iframe" => {
+                if let Some(src) = value.attr("src") {
+                    links.push(src, "iframe", ResourceKind::Frame, String::new());
+                }
+            }
+            "video" | "audio" => {
+                let element = if value.name() == "video" { "video" } else { "audio" };
+                if let Some(src) = value.attr("src") {
+                    links.push(src, element, ResourceKind::Media, String::new());
+                }
+                if let Some(poster) = value.attr("poster") {
+                    links.push(poster, element, ResourceKind::Image, String::new());
+                }
+            }
+            "style" => {
+                let css = el.text().collect::<String>();
+                for href in css_urls(&css) {
+                    links.push(href, "style", css_kind(href), String::new());
+                }
+            }
+            _ => {}
+        }
+    }
+
+    links.extracted
+}
+
+/// The `Location` of a redirect from `page_url`, scoped and filtered like a link on the page.
+pub fn redirect_link(location: &str, page_url: &Url, scope: &CrawlScope, filter: &UrlFilter) -> ExtractedLinks {
+    let mut links = LinkCollector {
+        page_url,
+        base: page_url.clone(),
+        scope,
+        filter,
+        extracted: ExtractedLinks::default(),
+    };
+    links.push(location, "redirect", ResourceKind::Page, String::new());
+    links.extracted
+}
+
+/// `noindex, nofollow` -> `{Noindex, RobotsNofollow}`; `none` means both.
+fn robots_directives(content: &str) -> Vec<Directive> {
+    let mut directives = Vec::new();
+    for token in content.split(',').map(|t| t.trim().to_lowercase()) {
+        match token.as_str() {
+            "noindex" => directives.push(Directive::Noindex),
+            "nofollow" => directives.push(Directive::RobotsNofollow),
+            "none" => directives.extend([Directive::Noindex, Directive::RobotsNofollow]),
+            _ => {}
+        }
+    }
+    directives
+}
+
+/// `5; url=/next` -> `/next`. A bare delay (a plain reload) has no target.
+fn refresh_target(content: &str) -> Option<&str> {
+    let (_, rest) = content.split_once([';', ','])?;
+    let rest = rest.trim();
+    let target = match rest.get(..3) {
+        Some(prefix) if prefix.eq_ignore_ascii_case("url") => rest[3..].trim_start().strip_prefix('=')?,
+        _ => rest,
+    };
+    let target = target.trim().trim_matches(['\'', '"']).trim();
+    (!target.is_empty()).then_some(target)
+}
+
+fn has_token(list: &str, token: &str) -> bool {
+    list.split_whitespace().any(|t| t.eq_ignore_ascii_case(token))
+}
+
+/// Classify a `<link>` by its `rel` (and `as` for preloads). Connection hints
+/// such as `preconnect` point at an origin, not a resource, and are skipped.
+fn link_kind(rel: &str, preload_as: Option<&str>) -> Option<ResourceKind> {
+    let rels: Vec<&str> = rel.split_whitespace().collect();
+    if !rels.is_empty() && rels.iter().all(|r| matches!(*r, "preconnect" | "dns-prefetch")) {
+        return None;
+    }
+
+    let kind = if rels.contains(&"stylesheet") {
+        ResourceKind::Stylesheet
+    } else if rels.iter().any(|r| r.contains("icon")) {
+        ResourceKind::Image
+    } else if rels.iter().any(|r| matches!(*r, "canonical" | "alternate" | "next" | "prev")) {
+        ResourceKind::Page
+    } else if rels.iter().any(|r| matches!(*r, "preload" | "prefetch" | "modulepreload")) {
+        match preload_as.unwrap_or_default() {
+            "style" => ResourceKind::Stylesheet,
+            "script" => ResourceKind::Script,
+            "font" => ResourceKind::Font,
+            "image" => ResourceKind::Image,
+            "video" | "audio" | "track" => ResourceKind::Media,
+            _ if rels.contains(&"modulepreload") => ResourceKind::Script,
+            _ => ResourceKind::Other,
+        }
+    } else {
+        ResourceKind::Other
+    };
+    Some(kind)
+}
+
+/// CSS `url()` references are mostly images; fonts and imports are told apart by extension.
+fn css_kind(href: &str) -> ResourceKind {
+    let path = href.split(['?', '#']).next().unwrap_or(href).to_lowercase();
+    if [".woff", ".woff2", ".ttf", ".otf", ".eot"].iter().any(|ext| path.ends_with(ext)) {
+        ResourceKind::Font
+    } else if path.ends_with(".css") {
+        ResourceKind::Stylesheet
+    } else {
+        ResourceKind::Image
+    }
+}
+
+fn css_urls(css: &str) -> Vec<&str> {
+    CSS_URL
+        .captures_iter(css)
+        .filter_map(|c| c.get(1))
+        .map(|m| m.as_str().trim())
         .collect()
 }
 
+/// `a.jpg 1x, b.jpg 2x` -> `[a.jpg, b.jpg]`. URLs may themselves contain commas,
+/// so candidates are split on the descriptor rather than on every comma.
+fn srcset_urls(srcset: &str) -> Vec<&str> {
+    let mut urls = Vec::new();
+    let mut rest = srcset;
+
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == ',');
+        if rest.is_empty() {
+            break;
+        }
+        let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        let (candidate, tail) = rest.split_at(end);
+        let url = candidate.trim_end_matches(',');
+        if !url.is_empty() {
+            urls.push(url);
+        }
+        // A trailing comma on the URL means there was no descriptor.
+        rest = if candidate.ends_with(',') {
+            tail
+        } else {
+            tail.find(',').map(|i| &tail[i + 1..]).unwrap_or("")
+        };
+    }
+
+    urls
+}
+
+/// Visible link text with whitespace collapsed, falling back to image alt text.
+fn anchor_text(el: &ElementRef) -> String {
+    let text = el.text().collect::<Vec<_>>().join(" ");
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    if !text.is_empty() {
+        return text;
+    }
+
+    el.select(&IMG_ALT)
+        .filter_map(|img| img.value().attr("alt"))
+        .map(|alt| alt.trim().to_string())
+        .find(|alt| !alt.is_empty())
+        .unwrap_or_default()
+}
+
 fn resolve_url(href: &str, base: &Url) -> Option<Url> {
     if href.starts_with("mailto:")
         || href.starts_with("javascript:")
         || href.starts_with("tel:")
+        || href.starts_with("data:")
         || href.starts_with('#')
     {
         return None;
@@ -28,8 +483,184 @@ fn resolve_url(href: &str, base: &Url) -> Option<Url> {
     base.join(href).ok()
 }
 
-fn is_same_domain(url: &Url, allowed_host: &str) -> bool {
-    url.host_str()
-        .map(|h| h == allowed_host)
-        .unwrap_or(false)
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scope::{ScopePolicy, ScopeSettings};
+
+    fn page() -> Url {
+        Url::parse("https://example.com/dir/page.html").unwrap()
+    }
+
+    fn extract(body: &str) -> ExtractedLinks {
+        let settings = ScopeSettings {
+            policy: ScopePolicy::Host,
+            allowed_hosts: Vec::new(),
+            strict_scheme: false,
+            external_resources: true,
+        };
+        let scope = CrawlScope::new(&page(), &settings);
+        extract_links(body, &page(), &scope, &UrlFilter::default())
+    }
+
+    /// Each link as `element kind url`.
+    fn summary(extracted: &ExtractedLinks) -> Vec<String> {
+        extracted
+            .links
+            .iter()
+            .map(|l| format!("{} {} {}", l.element, l.kind, l.url))
+            .collect()
+    }
+
+    #[test]
+    fn srcset_urls_split_on_descriptors_not_commas() {
+        assert_eq!(srcset_urls("a.jpg 1x, b.jpg 2x"), ["a.jpg", "b.jpg"]);
+        assert_eq!(srcset_urls("small.jpg 480w,large.jpg 1080w"), ["small.jpg", "large.jpg"]);
+        assert_eq!(srcset_urls("/img?size=1,2 1x, /img?size=3,4 2x"), ["/img?size=1,2", "/img?size=3,4"]);
+        assert_eq!(srcset_urls("only.jpg"), ["only.jpg"]);
+        assert_eq!(srcset_urls("a.jpg, b.jpg"), ["a.jpg", "b.jpg"]);
+        assert!(srcset_urls(" , ").is_empty());
+    }
+
+    #[test]
+    fn css_urls_take_every_quoting_style() {
+        let css = r#"background: url(a.png); src: url('f.woff2') format("woff2"), url( "b.svg" );"#;
+        assert_eq!(css_urls(css), ["a.png", "f.woff2", "b.svg"]);
+        assert_eq!(css_kind("f.woff2?v=3"), ResourceKind::Font);
+        assert_eq!(css_kind("theme.CSS"), ResourceKind::Stylesheet);
+        assert_eq!(css_kind("a.png#x"), ResourceKind::Image);
+    }
+
+    #[test]
+    fn link_kind_goes_by_rel_and_preload_type() {
+        assert_eq!(link_kind("stylesheet", None), Some(ResourceKind::Stylesheet));
+        assert_eq!(link_kind("shortcut icon", None), Some(ResourceKind::Image));
+        assert_eq!(link_kind("apple-touch-icon", None), Some(ResourceKind::Image));
+        assert_eq!(link_kind("canonical", None), Some(ResourceKind::Page));
+        assert_eq!(link_kind("preload", Some("font")), Some(ResourceKind::Font));
+        assert_eq!(link_kind("preload", Some("fetch")), Some(ResourceKind::Other));
+        assert_eq!(link_kind("modulepreload", None), Some(ResourceKind::Script));
+        assert_eq!(link_kind("manifest", None), Some(ResourceKind::Other));
+        assert_eq!(link_kind("preconnect dns-prefetch", None), None);
+    }
+
+    #[test]
+    fn resolve_url_skips_non_fetchable_schemes() {
+        let base = page();
+        assert_eq!(resolve_url("../up", &base).unwrap().as_str(), "https://example.com/up");
+        assert_eq!(resolve_url("//cdn.example.net/x.js", &base).unwrap().as_str(), "https://cdn.example.net/x.js");
+        for href in ["mailto:me@example.com", "javascript:void(0)", "tel:123", "data:image/png;base64,AA", "#top"] {
+            assert_eq!(resolve_url(href, &base), None, "{}", href);
+        }
+    }
+
+    #[test]
+    fn assets_are_extracted_with_their_kind() {
+        let body = r#"<html><head>
+            <link rel="stylesheet" href="/site.css"><link rel="preconnect" href="https://fonts.example.com">
+            <script src="app.js"></script>
+            <style>body { background: url(/bg.png) }</style>
+        </head><body>
+            <a href="/about#team">About <b>us</b></a>
+            <a href="/home"><img src="/logo.png" alt=" Home "></a>
+            <img srcset="/s.jpg 1x, /l.jpg 2x">
+            <video src="/clip.mp4" poster="/poster.jpg"><source src="/clip.webm"></video>
+            <picture><source srcset="/p.webp"></picture>
+            <iframe src="/embed"></iframe>
+            <div style="background-image: url('/tile.gif')"></div>
+            <a href="mailto:me@example.com">Mail</a>
+        </body></html>"#;
+        let extracted = extract(body);
+        assert_eq!(
+            summary(&extracted),
+            [
+                "link stylesheet https://example.com/site.css",
+                "script script https://example.com/dir/app.js",
+                "style image https://example.com/bg.png",
+                "a page https://example.com/about",
+                "a page https://example.com/home",
+                "img image https://example.com/logo.png",
+                "img image https://example.com/s.jpg",
+                "img image https://example.com/l.jpg",
+                "video media https://example.com/clip.mp4",
+                "video image https://example.com/poster.jpg",
+                "source media https://example.com/clip.webm",
+                "source image https://example.com/p.webp",
+                "iframe frame https://example.com/embed",
+                "style image https://example.com/tile.gif",
+            ]
+        );
+        let anchors: Vec<&str> = extracted.links.iter().map(|l| l.anchor_text.as_str()).collect();
+        assert_eq!(anchors[3..6], ["About us", "Home", "Home"]);
+    }
+
+    #[test]
+    fn refresh_target_reads_the_url_after_the_delay() {
+        assert_eq!(refresh_target("5; url=/next"), Some("/next"));
+        assert_eq!(refresh_target("0;URL='https://example.com/x'"), Some("https://example.com/x"));
+        assert_eq!(refresh_target("3, url = \"/quoted\" "), Some("/quoted"));
+        assert_eq!(refresh_target("1; /bare"), Some("/bare"));
+        assert_eq!(refresh_target("30"), None);
+        assert_eq!(refresh_target("0; url="), None);
+    }
+
+    #[test]
+    fn robots_directives_read_noindex_nofollow_and_none() {
+        assert_eq!(robots_directives("NoIndex, follow"), [Directive::Noindex]);
+        assert_eq!(robots_directives("index,nofollow"), [Directive::RobotsNofollow]);
+        assert_eq!(robots_directives("none"), [Directive::Noindex, Directive::RobotsNofollow]);
+        assert!(robots_directives("all").is_empty());
+    }
+
+    #[test]
+    fn rel_nofollow_marks_page_links_only() {
+        let body = r#"<a href="/a" rel="external NoFollow">A</a><a href="/b">B</a>
+            <link rel="nofollow stylesheet" href="/s.css">"#;
+        let extracted = extract(body);
+        let nofollow: Vec<bool> = extracted.links.iter().map(ExtractedLink::is_nofollow).collect();
+        assert_eq!(nofollow, [true, false, false]);
+        assert!(extracted.page_directives.is_empty());
+    }
+
+    #[test]
+    fn meta_robots_nofollow_applies_to_every_page_link() {
+        let body = r#"<head><meta name="ROBOTS" content="noindex, nofollow"></head>
+            <body><a href="/a">A</a><img src="/i.png"></body>"#;
+        let extracted = extract(body);
+        assert_eq!(
+            extracted.page_directives,
+            BTreeSet::from([Directive::Noindex, Directive::RobotsNofollow])
+        );
+        let nofollow: Vec<bool> = extracted.links.iter().map(ExtractedLink::is_nofollow).collect();
+        assert_eq!(nofollow, [true, false]);
+    }
+
+    #[test]
+    fn meta_refresh_targets_are_page_links() {
+        let extracted = extract(r#"<meta http-equiv="Refresh" content="0; url=moved.html">"#);
+        let link = &extracted.links[0];
+        assert_eq!((link.element, link.kind), ("meta", ResourceKind::Page));
+        assert_eq!(link.url.as_str(), "https://example.com/dir/moved.html");
+        assert_eq!(link.directives, BTreeSet::from([Directive::MetaRefresh]));
+    }
+
+    #[test]
+    fn base_href_resolves_relative_links_and_is_noted() {
+        let body = r#"<base href="/other/"><base href="/ignored/">
+            <a href="x.html">X</a><a href="/abs">Abs</a><a href="https://example.com/full">Full</a>"#;
+        let extracted = extract(body);
+        let links: Vec<(&str, bool)> = extracted
+            .links
+            .iter()
+            .map(|l| (l.url.as_str(), l.directives.contains(&Directive::BaseHref)))
+            .collect();
+        assert_eq!(
+            links,
+            [
+                ("https://example.com/other/x.html", true),
+                ("https://example.com/abs", false),
+                ("https://example.com/full", false),
+            ]
+        );
+    }
 }
@@ -2,30 +2,53 @@ use reqwest::Client;
 use tracing::{warn, error, debug};
 use url::Url;
 
-use crate::client::get_with_retry;
-pub async fn fetch_page(client: &Client, url: &Url, max_retries: u32) -> Option<String> {
-    match get_with_retry(client, url.as_str(), max_retries).await {
-        Ok(resp) => {
-            let status = resp.status();
+use crate::checker::Outcome;
+use crate::client::{get_body_with_retry, Pacing};
+use crate::retry::RetryPolicy;
+use crate::scheduler::Permit;
+
+fn is_html(resp: &reqwest::Response) -> bool {
+    resp.headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+        .to_lowercase()
+        .contains("text/html")
+}
+
+/// Fetch a page for crawling. The outcome is what a check of `url` would have
+/// reported; the body is only read for successful HTML responses.
+pub async fn fetch_page(
+    client: &Client,
+    url: &Url,
+    retry: &RetryPolicy,
+    permit: &Permit,
+) -> (Outcome, Option<String>) {
+    let accept = |resp: &reqwest::Response| resp.status().is_success() && is_html(resp);
+    let attempts = get_body_with_retry(client, url.as_str(), retry, Pacing::Permit(permit), accept).await;
+    match attempts.result {
+        Ok(fetched) => {
+            let status = fetched.status;
+            let outcome = Outcome::response(status, &fetched.headers, attempts.retries, attempts.elapsed);
+            if status.is_redirection() {
+                debug!(status = %status, url = %url, location = ?outcome.redirect_url, "Redirect");
+                return (outcome, None);
+            }
             if !status.is_success() {
                 warn!(status = %status, url = %url, "Non-success HTTP status");
-                return None;
-            }
-            let content_type = resp
-                .headers()
-                .get(reqwest::header::CONTENT_TYPE)
-                .and_then(|v| v.to_str().ok())
-                .unwrap_or_default()
-                .to_lowercase();
-            if !content_type.contains("text/html") {
-                debug!(url = %url, content_type = %content_type, "Skipping non-HTML");
-                return None;
+                return (outcome, None);
             }
-            match resp.text().await {
-                Ok(body) => Some(body),
-                Err(e) => {
-                    error!(url = %url, error = %e, "Failed to read response body");
-                    None
+            match fetched.body {
+                Some(body) => (outcome, Some(String::from_utf8_lossy(&body).into_owned())),
+                None => {
+                    let content_type = fetched
+                        .headers
+                        .get(reqwest::header::CONTENT_TYPE)
+                        .and_then(|v| v.to_str().ok())
+                        .unwrap_or_default()
+                        .to_lowercase();
+                    debug!(url = %url, content_type = %content_type, "Skipping non-HTML");
+                    (outcome, None)
                 }
             }
         }
@@ -34,10 +57,12 @@ pub async fn fetch_page(client: &Client, url: &Url, max_retries: u32) -> Option<
                 warn!(url = %url, "Request timed out");
             } else if e.is_connect() {
                 warn!(url = %url, error = %e, "Connection error");
+            } else if e.is_body() {
+                error!(url = %url, error = %e, "Failed to read response body");
             } else {
                 error!(url = %url, error = %e, "Request failed");
             }
-            None
+            (Outcome::error(&e, attempts.retries), None)
         }
     }
 }
@@ -0,0 +1,109 @@
+use regex::Regex;
+
+use crate::config::AppConfig;
+
+/// Parse a `--include`/`--exclude` pattern.
+///
+/// Patterns are regular expressions searched anywhere in the URL. A `glob:`
+/// prefix switches to a glob where `*` matches any run of characters and
+/// everything else is literal, e.g. `glob:/tag/*` or `glob:*?sort=*`.
+pub fn parse_pattern(pattern: &str) -> Result<Regex, String> {
+    let source = match pattern.strip_prefix("glob:") {
+        Some(glob) => glob
+            .split('*')
+            .map(regex::escape)
+            .collect::<Vec<_>>()
+            .join(".*"),
+        None => pattern.strip_prefix("regex:").unwrap_or(pattern).to_string(),
+    };
+
+    Regex::new(&source).map_err(|e| e.to_string())
+}
+
+/// Include/exclude URL filters shared by crawl and sitemap mode.
+#[derive(Debug, Clone, Default)]
+pub struct UrlFilter {
+    include: Vec<Regex>,
+    exclude: Vec<Regex>,
+}
+
+impl UrlFilter {
+    pub fn from_config(config: &AppConfig) -> Self {
+        Self {
+            include: config.include.clone(),
+            exclude: config.exclude.clone(),
+        }
+    }
+
+    /// A URL passes when it matches any include pattern (or there are none)
+    /// and no exclude pattern.
+    pub fn is_allowed(&self, url: &str) -> bool {
+        let included = self.include.is_empty() || self.include.iter().any(|re| re.is_match(url));
+        included && !self.exclude.iter().any(|re| re.is_match(url))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(include: &[&str], exclude: &[&str]) -> UrlFilter {
+        let parse = |patterns: &[&str]| patterns.iter().map(|p| parse_pattern(p).unwrap()).collect();
+        UrlFilter {
+            include: parse(include),
+            exclude: parse(exclude),
+        }
+    }
+
+    #[test]
+    fn regex_is_searched_anywhere_in_the_url() {
+        let re = parse_pattern(r"/blog/\d+").unwrap();
+        assert!(re.is_match("https://example.com/blog/2024/post"));
+        assert!(!re.is_match("https://example.com/blog/latest"));
+    }
+
+    #[test]
+    fn regex_prefix_is_optional() {
+        let re = parse_pattern("regex:^https://example\\.com/$").unwrap();
+        assert!(re.is_match("https://example.com/"));
+        assert!(!re.is_match("https://example.com/about"));
+    }
+
+    #[test]
+    fn glob_star_matches_any_run_and_the_rest_is_literal() {
+        let re = parse_pattern("glob:*?sort=*").unwrap();
+        assert!(re.is_match("https://example.com/list?sort=asc"));
+        assert!(!re.is_match("https://example.com/listXsort=asc"));
+
+        let re = parse_pattern("glob:/tag/*.html").unwrap();
+        assert!(re.is_match("https://example.com/tag/rust.html"));
+        assert!(!re.is_match("https://example.com/tag/rust.htm"));
+    }
+
+    #[test]
+    fn invalid_regex_is_an_error() {
+        assert!(parse_pattern("(unclosed").is_err());
+        assert!(parse_pattern("glob:(unclosed").is_ok());
+    }
+
+    #[test]
+    fn no_patterns_allow_everything() {
+        assert!(filter(&[], &[]).is_allowed("https://example.com/anything"));
+    }
+
+    #[test]
+    fn url_must_match_some_include() {
+        let f = filter(&["/docs/", "/blog/"], &[]);
+        assert!(f.is_allowed("https://example.com/docs/intro"));
+        assert!(f.is_allowed("https://example.com/blog/post"));
+        assert!(!f.is_allowed("https://example.com/shop/item"));
+    }
+
+    #[test]
+    fn exclude_wins_over_include() {
+        let f = filter(&["/docs/"], &["glob:*/draft/*"]);
+        assert!(f.is_allowed("https://example.com/docs/intro"));
+        assert!(!f.is_allowed("https://example.com/docs/draft/intro"));
+        assert!(!filter(&[], &["/private"]).is_allowed("https://example.com/private/x"));
+    }
+}
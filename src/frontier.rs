@@ -0,0 +1,234 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use tracing::{debug, warn};
+use url::Url;
+
+use crate::crawler::Visited;
+use crate::state::{page_fields, parse_page};
+
+/// Pages queued in memory before the rest of the frontier goes to disk.
+const IN_MEMORY: usize = 100_000;
+
+/// Tells the spill files of crawls running at the same time apart.
+static SPILLS: AtomicUsize = AtomicUsize::new(0);
+
+/// Pages waiting to be crawled, in the order they were found, together with
+/// the records of pages being crawled. Past `IN_MEMORY` queued pages, newly
+/// found ones are appended to a temporary file with their records and read
+/// back once the pages before them have been taken, so the memory a crawl
+/// needs does not grow with the size of its frontier.
+#[derive(Default)]
+pub struct Frontier {
+    /// URLs queued in memory; their records are in `pages`.
+    queue: VecDeque<String>,
+    /// Records of the pages queued in memory or being crawled.
+    pages: HashMap<String, Visited>,
+    spill: Option<Spill>,
+    /// Pages in the spill file not read back yet.
+    spilled: usize,
+}
+
+impl Frontier {
+    /// Queue `url` to be crawled after everything queued before it.
+    pub fn push(&mut self, url: String, page: Visited) {
+        if self.spilled > 0 || self.queue.len() >= IN_MEMORY {
+            match self.spill_page(&url, &page) {
+                Ok(()) => {
+                    self.spilled += 1;
+                    return;
+                }
+                Err(e) => warn!(error = %e, "Failed to queue page on disk, keeping it in memory"),
+            }
+        }
+        self.queue.push_back(url.clone());
+        self.pages.insert(url, page);
+    }
+
+    /// The next page to crawl, with its depth and whether it is reachable from
+    /// the start URL. Its record stays here until it is `remove`d.
+    pub fn pop(&mut self) -> Option<(Url, u32, bool)> {
+        loop {
+            let url = match self.queue.pop_front() {
+                Some(url) => url,
+                None => {
+                    let (url, page) = self.read_spilled()?;
+                    self.pages.insert(url.clone(), page);
+                    url
+                }
+            };
+            let page = &self.pages[&url];
+            match Url::parse(&url) {
+                Ok(parsed) => return Some((parsed, page.depth, page.reachable)),
+                Err(_) => {
+                    self.pages.remove(&url);
+                }
+            }
+        }
+    }
+
+    /// Nothing is left to crawl, though pages may still be being crawled.
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty() && self.spilled == 0
+    }
+
+    /// The record of a page queued in memory or being crawled. Pages queued
+    /// on disk are not found.
+    pub fn get_mut(&mut self, url: &str) -> Option<&mut Visited> {
+        self.pages.get_mut(url)
+    }
+
+    /// Take the record of a page that has been crawled.
+    pub fn remove(&mut self, url: &str) -> Option<Visited> {
+        self.pages.remove(url)
+    }
+
+    /// Take any page left, queued or not, once crawling has stopped.
+    pub fn take_left(&mut self) -> Option<(String, Visited)> {
+        while let Some(url) = self.queue.pop_front() {
+            if let Some(page) = self.pages.remove(&url) {
+                return Some((url, page));
+            }
+        }
+        // Pages still being crawled when it stopped.
+        if let Some(url) = self.pages.keys().next().cloned() {
+            let page = self.pages.remove(&url)?;
+            return Some((url, page));
+        }
+        self.read_spilled()
+    }
+
+    fn spill_page(&mut self, url: &str, page: &Visited) -> std::io::Result<()> {
+        if self.spill.is_none() {
+            self.spill = Some(Spill::create()?);
+        }
+        let spill = self.spill.as_mut().expect("just created");
+        let [depth, reachable, kind, directives, aliases] = page_fields(page);
+        writeln!(spill.writer, "{}\t{}\t{}\t{}\t{}\t{}", url, depth, reachable, kind, directives, aliases)
+    }
+
+    /// The oldest page on disk, if any.
+    fn read_spilled(&mut self) -> Option<(String, Visited)> {
+        while self.spilled > 0 {
+            let spill = self.spill.as_mut()?;
+            self.spilled -= 1;
+            let line = spill.read_line();
+            if self.spilled == 0 {
+                // Everything was read back, so the file can start over.
+                if let Err(e) = spill.clear() {
+                    warn!(error = %e, "Failed to reset the on-disk frontier");
+                    self.spill = None;
+                }
+            }
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => {
+                    warn!(error = %e, pages = self.spilled + 1, "Failed to read pages queued on disk, dropping them");
+                    self.spilled = 0;
+                    self.spill = None;
+                    return None;
+                }
+            };
+            let fields: Vec<&str> = line.trim_end_matches('\n').split('\t').collect();
+            if let [url, depth, reachable, kind, directives, aliases] = fields.as_slice() {
+                if let Some(page) = parse_page(depth, reachable, kind, directives, aliases) {
+                    return Some((url.to_string(), page));
+                }
+            }
+            debug!(line = %line, "Skipped unreadable frontier record");
+        }
+        None
+    }
+}
+
+/// A temporary file holding the frontier past `IN_MEMORY`, removed when dropped.
+struct Spill {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    reader: BufReader<File>,
+}
+
+impl Spill {
+    fn create() -> std::io::Result<Self> {
+        let id = SPILLS.fetch_add(1, Ordering::Relaxed);
+        let path = std::env::temp_dir().join(format!("sitemap-crawl-{}-frontier-{}", std::process::id(), id));
+        let file = OpenOptions::new().write(true).create(true).truncate(true).open(&path)?;
+        // Opened apart from the writer, so each keeps its own position.
+        let reader = BufReader::new(File::open(&path)?);
+        debug!(path = %path.display(), "Queueing the rest of the frontier on disk");
+        Ok(Self { path, writer: BufWriter::new(file), reader })
+    }
+
+    fn read_line(&mut self) -> std::io::Result<String> {
+        self.writer.flush()?;
+        let mut line = String::new();
+        self.reader.read_line(&mut line)?;
+        Ok(line)
+    }
+
+    fn clear(&mut self) -> std::io::Result<()> {
+        self.writer.flush()?;
+        self.writer.get_mut().set_len(0)?;
+        self.writer.seek(SeekFrom::Start(0))?;
+        self.reader.seek(SeekFrom::Start(0))?;
+        Ok(())
+    }
+}
+
+impl Drop for Spill {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(depth: u32) -> Visited {
+        Visited {
+            depth,
+            reachable: true,
+            aliases: vec![format!("https://example.com/{}?utm_source=x", depth)],
+            ..Visited::default()
+        }
+    }
+
+    #[test]
+    fn pages_past_the_memory_limit_come_back_in_order() {
+        let mut frontier = Frontier::default();
+        let total = IN_MEMORY as u32 + 3;
+        for depth in 0..total {
+            frontier.push(format!("https://example.com/{}", depth), page(depth));
+        }
+        assert_eq!(frontier.spilled, 3);
+
+        for depth in 0..total {
+            let (url, popped, reachable) = frontier.pop().expect("page left");
+            assert_eq!(url.as_str(), format!("https://example.com/{}", depth));
+            assert_eq!((popped, reachable), (depth, true));
+            let record = frontier.remove(url.as_str()).expect("record kept while crawling");
+            assert_eq!(record.aliases, page(depth).aliases);
+        }
+        assert!(frontier.is_empty());
+        assert!(frontier.pop().is_none());
+    }
+
+    #[test]
+    fn take_left_includes_pages_on_disk() {
+        let mut frontier = Frontier::default();
+        let total = IN_MEMORY + 2;
+        for depth in 0..total as u32 {
+            frontier.push(format!("https://example.com/{}", depth), page(depth));
+        }
+        let mut left = 0;
+        while frontier.take_left().is_some() {
+            left += 1;
+        }
+        assert_eq!(left, total);
+        assert!(frontier.is_empty());
+    }
+}
@@ -0,0 +1,54 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Only the first sources of each target are kept; nav links would otherwise
+/// add an edge from every page on the site.
+const MAX_EDGES_PER_TARGET: usize = 50;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinkEdge {
+    /// Page the link was found on.
+    pub source: String,
+    pub anchor_text: String,
+    /// HTML element that carried the link, e.g. `a`.
+    pub element: &'static str,
+}
+
+#[derive(Debug, Default)]
+struct Inlinks {
+    edges: Vec<LinkEdge>,
+    total: usize,
+}
+
+/// Source -> target edges discovered while crawling, indexed by target URL.
+#[derive(Debug, Default)]
+pub struct LinkGraph {
+    targets: HashMap<String, Inlinks>,
+}
+
+pub type SharedLinkGraph = Arc<Mutex<LinkGraph>>;
+
+impl LinkGraph {
+    pub fn add(&mut self, target: &str, edge: LinkEdge) {
+        let inlinks = self.targets.entry(target.to_string()).or_default();
+        if inlinks.edges.contains(&edge) {
+            return;
+        }
+        inlinks.total += 1;
+        if inlinks.edges.len() < MAX_EDGES_PER_TARGET {
+            inlinks.edges.push(edge);
+        }
+    }
+
+    /// The recorded edges pointing at `target`, oldest first.
+    pub fn inlinks(&self, target: &str) -> &[LinkEdge] {
+        self.targets
+            .get(target)
+            .map(|i| i.edges.as_slice())
+            .unwrap_or_default()
+    }
+
+    pub fn edge_count(&self) -> usize {
+        self.targets.values().map(|i| i.total).sum()
+    }
+}
@@ -2,21 +2,55 @@ mod checker;
 mod client;
 mod config;
 mod crawler;
+mod discovery;
 mod export;
 mod extractor;
 mod fetcher;
+mod filter;
+mod frontier;
+mod link_graph;
+mod normalize;
+mod pipeline;
+mod reconcile;
+mod report;
+mod retry;
+mod robots;
+mod scheduler;
+mod scope;
+// The sitemap XML generator is not wired into the CLI yet.
+#[allow(dead_code)]
 mod sitemap;
 mod sitemap_parser;
+mod state;
+mod throttle;
 mod user_agents;
+mod validate;
+mod visited;
 
-use checker::{check_urls_stream, UrlStatus};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use checker::{check_urls_stream, CheckTarget, UrlStatus};
 use clap::Parser;
-use config::{AppConfig, is_sitemap_url};
-use crawler::crawl;
-use export::CsvWriter;
+use client::build_client;
+use config::{AppConfig, Mode, is_sitemap_url};
+use crawler::{crawl, CrawlOptions};
+use discovery::discover_sitemaps;
+use export::{CsvWriter, Row, XlsxWriter};
+use extractor::ResourceKind;
 use indicatif::{ProgressBar, ProgressStyle};
+use pipeline::{TargetSink, STATUS_QUEUE};
+use retry::RetryPolicy;
+use report::{output_path, RunReport, Summary};
+use robots::{RobotsCache, BLOCKED_STATUS_TEXT};
+use scheduler::{HostScheduler, SchedulerLimits};
 use sitemap_parser::parse_sitemap;
-use tracing::info;
+use state::StateStore;
+use throttle::Throttle;
+use tracing::{info, warn};
+use url::Url;
+use validate::validate;
+use visited::VisitedSettings;
 
 #[tokio::main]
 async fn main() {
@@ -31,32 +65,45 @@ async fn main() {
 
     info!(
         urls = config.urls.len(),
+        mode = %config.mode,
         concurrency = config.concurrency,
+        host_concurrency = config.host_concurrency,
         timeout = format!("{}s", config.timeout),
         delay = format!("{}ms", config.delay),
+        visited_set = %VisitedSettings::from_config(&config),
         "Starting sitemap-crawl"
     );
 
-    let mut all_discovered: Vec<String> = Vec::new();
-
-    for input_url in &config.urls {
-        if is_sitemap_url(input_url) {
-            info!(url = %input_url, "Parsing sitemap");
-            let urls = parse_sitemap(input_url, config.proxy.as_deref(), config.retry).await;
-            info!(count = urls.len(), url = %input_url, "Found URLs from sitemap");
-            all_discovered.extend(urls);
-        } else {
-            info!(url = %input_url, "Crawling website");
-            let urls = crawl(input_url, config.proxy.as_deref(), config.retry).await;
-            info!(count = urls.len(), url = %input_url, "Found URLs from crawl");
-            all_discovered.extend(urls);
-        }
+    let retry = RetryPolicy::from_config(&config);
+    // Shared by every request the run makes, whatever it is for.
+    let throttle = Arc::new(Throttle::from_config(&config));
+    if config.mode == Mode::Validate {
+        let violations = validate(&config, &retry, &throttle).await;
+        std::process::exit(if violations > 0 { 1 } else { 0 });
     }
 
-    all_discovered.sort();
-    all_discovered.dedup();
+    let robots = if config.ignore_robots {
+        info!("Ignoring robots.txt");
+        None
+    } else {
+        let client = build_client(config.proxy.as_deref()).expect("Failed to build HTTP client");
+        Some(Arc::new(RobotsCache::new(client, &config.robots_agent, retry.clone(), Arc::clone(&throttle))))
+    };
 
-    info!(count = all_discovered.len(), "Total unique URLs to check");
+    let state = config.resume.as_deref().map(|dir| {
+        info!(dir = %dir.display(), "Saving progress for --resume");
+        Arc::new(StateStore::open(dir).expect("Failed to open state directory"))
+    });
+
+    let scheduler = HostScheduler::new(SchedulerLimits::from_config(&config), Arc::clone(&throttle));
+    let mut report = RunReport::new(&config);
+    let crawl_options = CrawlOptions::from_config(&config, robots.clone(), scheduler.clone(), state.clone());
+    let filter = Arc::clone(&crawl_options.filter);
+    let normalizer = Arc::clone(&crawl_options.normalizer);
+    let link_graph = Arc::clone(&crawl_options.link_graph);
+    if let Some(state) = &state {
+        state.restore_links(&mut link_graph.lock().expect("link graph poisoned"));
+    }
 
     let output_dir = "result/";
     std::fs::create_dir_all(output_dir).expect("Failed to create result/ directory");
@@ -69,10 +116,18 @@ async fn main() {
     let mut csv_writer = CsvWriter::new(&csv_path).expect("Failed to create CSV writer");
     info!(path = %csv_path, "Streaming results to CSV");
 
-    let total = all_discovered.len();
-    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<UrlStatus>();
+    let mut xlsx_writer = config.xlsx.then(|| {
+        let path = output_path(&csv_path, "xlsx");
+        XlsxWriter::new(&path.to_string_lossy()).expect("Failed to create XLSX writer")
+    });
+
+    // Discovery feeds the checker as it goes, so both run at the same time.
+    let (sink, targets) = TargetSink::channel(crawl_options.visited.build());
+    let queued = sink.queued();
+    let merges = sink.merges();
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<UrlStatus>(STATUS_QUEUE);
 
-    let pb = ProgressBar::new(total as u64);
+    let pb = ProgressBar::new(0);
     pb.set_style(
         ProgressStyle::with_template(
             "{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} ({per_sec}) {msg}"
@@ -83,53 +138,210 @@ async fn main() {
     pb.set_message("Checking URLs...");
 
     let check_config = config.clone();
+    let check_scheduler = scheduler.clone();
+    let check_robots = robots.clone();
     let check_handle = tokio::spawn(async move {
-        check_urls_stream(&all_discovered, &check_config, tx).await;
+        check_urls_stream(targets, &check_config, check_robots, check_scheduler, state, tx).await;
     });
 
-    let mut all_results: Vec<UrlStatus> = Vec::with_capacity(total);
+    let discover = async {
+        // Owned here, so the checker stops waiting once discovery is done.
+        let sink = sink;
+
+        for input_url in &config.urls {
+            let sitemaps = match config.mode {
+                Mode::Auto | Mode::Hybrid if is_sitemap_url(input_url) => vec![input_url.clone()],
+                Mode::Sitemap | Mode::Validate => vec![input_url.clone()],
+                Mode::Discover | Mode::Hybrid => {
+                    info!(url = %input_url, "Discovering sitemaps");
+                    let client = build_client(config.proxy.as_deref()).expect("Failed to build HTTP client");
+                    let found = discover_sitemaps(
+                        input_url,
+                        &client,
+                        robots.as_deref(),
+                        &config.robots_agent,
+                        &retry,
+                        &throttle,
+                    )
+                    .await;
+                    if found.is_empty() {
+                        warn!(url = %input_url, "No sitemaps found, falling back to crawl");
+                    }
+                    report.add_sitemaps(&found);
+                    found.into_iter().map(|d| d.url).collect()
+                }
+                Mode::Auto | Mode::Crawl => Vec::new(),
+            };
+
+            // Only hybrid mode needs the sitemap URLs again after queueing them.
+            let hybrid = config.mode == Mode::Hybrid;
+            let mut sitemap_targets: Vec<CheckTarget> = Vec::new();
+            let mut listed: HashMap<String, usize> = HashMap::new();
+            for sitemap_url in &sitemaps {
+                info!(url = %sitemap_url, "Parsing sitemap");
+                let mut stream = parse_sitemap(sitemap_url, config.proxy.as_deref(), &retry, &throttle);
+                let (mut found, mut excluded) = (0usize, 0usize);
+                while let Some(entry) = stream.entries.recv().await {
+                    found += 1;
+                    if !filter.is_allowed(&entry.loc) {
+                        excluded += 1;
+                        continue;
+                    }
+                    let url = normalizer.normalize_str(&entry.loc);
+                    let aliases = if url != entry.loc { vec![entry.loc.clone()] } else { Vec::new() };
+                    let target = CheckTarget {
+                        url,
+                        depth: None,
+                        aliases,
+                        kind: ResourceKind::Page,
+                        directives: Vec::new(),
+                        coverage: None,
+                        sitemap: Some(entry),
+                        fetched: None,
+                    };
+                    if !hybrid {
+                        sink.send(target).await;
+                    } else if let Some(&index) = listed.get(&target.url) {
+                        // Listed again, maybe under another spelling.
+                        let known = &mut sitemap_targets[index];
+                        for alias in target.aliases {
+                            if !known.aliases.contains(&alias) {
+                                known.aliases.push(alias);
+                            }
+                        }
+                    } else {
+                        listed.insert(target.url.clone(), sitemap_targets.len());
+                        sitemap_targets.push(target);
+                    }
+                }
+                info!(count = found, url = %sitemap_url, "Found URLs from sitemap");
+                if excluded > 0 {
+                    info!(count = excluded, url = %sitemap_url, "Excluded URLs by filter");
+                }
+                report.add_excluded(excluded);
+                report.add_partial_sitemaps(stream.partial().await);
+            }
+
+            if !sitemaps.is_empty() && !hybrid {
+                continue;
+            }
+
+            // A sitemap given directly in hybrid mode is crawled from its site root.
+            let start_url = if is_sitemap_url(input_url) {
+                Url::parse(input_url)
+                    .and_then(|u| u.join("/"))
+                    .map(String::from)
+                    .unwrap_or_else(|_| input_url.clone())
+            } else {
+                input_url.clone()
+            };
+            let seeds: Vec<String> = sitemap_targets.iter().map(|t| t.url.clone()).collect();
+            let crawl_sink = if hybrid {
+                sink.reconciling(&sitemap_targets)
+            } else {
+                sink.clone()
+            };
+
+            info!(url = %start_url, seeds = seeds.len(), "Crawling website");
+            let result = crawl(&start_url, &seeds, &crawl_options, &crawl_sink).await;
+            info!(count = result.found, url = %start_url, "Found URLs from crawl");
+            if let Some(limit) = result.limit_reached {
+                report.add_limit_reached(&start_url, limit);
+            }
+            report.add_scope(&start_url, &result.scope);
+            report.add_excluded(result.excluded);
+            report.add_nofollowed(result.nofollowed);
+
+            // Sitemap URLs the crawler never saw (out of scope, blocked, or past a limit).
+            for target in sitemap_targets {
+                crawl_sink.send(target).await;
+            }
+        }
+
+        info!(count = queued.get(), "Discovery complete, total unique URLs to check");
+    };
+
+    let mut summary = Summary::default();
     let mut ok_count: usize = 0;
     let mut err_count: usize = 0;
 
-    while let Some(status) = rx.recv().await {
-        csv_writer.append_row(&status).expect("Failed to write CSV row");
-
-        match status.status_code {
-            Some(200..=299) => ok_count += 1,
-            Some(code) if code >= 400 => {
-                err_count += 1;
-                pb.println(format!("  ⚠ {} [{}]", status.url, code));
+    let write_results = async {
+        while let Some(status) = rx.recv().await {
+            {
+                // Links found later are added when the CSV is finished.
+                let graph = link_graph.lock().expect("link graph poisoned");
+                csv_writer.append_row(&status, graph.inlinks(&status.url)).expect("Failed to write CSV row");
             }
-            None => {
-                err_count += 1;
-                pb.println(format!("  ✗ {} [{}]", status.url, status.status_text));
+
+            match status.status_code {
+                Some(200..=299) => ok_count += 1,
+                None if status.status_text == BLOCKED_STATUS_TEXT => {}
+                Some(code) if code >= 400 => {
+                    err_count += 1;
+                    pb.println(format!("  ⚠ {}{} [{}]", kind_prefix(status.kind), status.url, code));
+                }
+                None => {
+                    err_count += 1;
+                    pb.println(format!("  ✗ {}{} [{}]", kind_prefix(status.kind), status.url, status.status_text));
+                }
+                _ => {}
             }
-            _ => {}
+
+            summary.record(&status);
+            pb.set_length(queued.get() as u64);
+            pb.set_message(format!("✅ {} ❌ {}", ok_count, err_count));
+            pb.inc(1);
         }
+    };
 
-        all_results.push(status);
-        pb.set_message(format!("✅ {} ❌ {}", ok_count, err_count));
-        pb.inc(1);
-    }
+    tokio::join!(discover, write_results);
+    report.summary = summary;
 
     check_handle.await.expect("Checker task panicked");
     pb.finish_with_message(format!("Done — ✅ {} ❌ {}", ok_count, err_count));
 
-    info!(path = %csv_path, rows = csv_writer.row_count(), "CSV export complete");
+    // Every link and duplicate is known now, so each row gets all of its
+    // inlinks and aliases.
+    let rows = csv_writer.row_count();
+    let mut late_aliases = 0;
+    let finished = {
+        let graph = link_graph.lock().expect("link graph poisoned");
+        let complete = |row: &mut Row| {
+            row.set_inlinks(graph.inlinks(row.url()));
+            if let Some(merged) = merges.take(row.url()) {
+                late_aliases += row.merge(merged);
+            }
+        };
+        csv_writer.finish(complete, xlsx_writer.as_mut())
+    };
+    report.summary.aliases_merged += late_aliases;
+    match finished {
+        Ok(path) => info!(path = %path, rows, "CSV export complete"),
+        Err(e) => warn!(path = %csv_path, error = %e, "Failed to fill in the final link and alias columns"),
+    }
 
-    let ok_count = all_results.iter().filter(|r| matches!(r.status_code, Some(200..=299))).count();
-    let redirect_count = all_results.iter().filter(|r| matches!(r.status_code, Some(300..=399))).count();
-    let client_err_count = all_results.iter().filter(|r| matches!(r.status_code, Some(400..=499))).count();
-    let server_err_count = all_results.iter().filter(|r| matches!(r.status_code, Some(500..=599))).count();
-    let error_count = all_results.iter().filter(|r| r.status_code.is_none()).count();
+    if let Some(xlsx) = xlsx_writer {
+        match xlsx.finish() {
+            Ok(path) => info!(path = %path, "XLSX export complete"),
+            Err(e) => warn!(error = %e, "Failed to save XLSX"),
+        }
+    }
 
-    info!(
-        total = all_results.len(),
-        ok_2xx = ok_count,
-        redirect_3xx = redirect_count,
-        client_err_4xx = client_err_count,
-        server_err_5xx = server_err_count,
-        connection_err = error_count,
-        "Status check complete"
-    );
+    report.set_host_rates(scheduler.rate_summary());
+    report.set_circuits_opened(scheduler.circuit_summary());
+    report.set_link_count(link_graph.lock().expect("link graph poisoned").edge_count());
+    report.log();
+    let report_file = output_path(&csv_path, "report.txt");
+    match report.write(&report_file) {
+        Ok(()) => info!(path = %report_file.display(), "Run report written"),
+        Err(e) => warn!(path = %report_file.display(), error = %e, "Failed to write run report"),
+    }
+}
+
+/// `[image] ` for assets, nothing for pages, so broken assets stand out in the console.
+fn kind_prefix(kind: ResourceKind) -> String {
+    match kind {
+        ResourceKind::Page => String::new(),
+        other => format!("[{}] ", other),
+    }
 }
@@ -0,0 +1,235 @@
+use clap::ValueEnum;
+use url::Url;
+
+use crate::config::AppConfig;
+
+/// Query parameters that only carry campaign/click tracking.
+const TRACKING_PARAMS: &[&str] = &[
+    "gclid", "gbraid", "wbraid", "dclid", "fbclid", "msclkid", "yclid", "mc_cid", "mc_eid",
+    "_hsenc", "_hsmi",
+];
+
+/// File names that serve the same content as their directory.
+const INDEX_FILES: &[&str] = &["index.html", "index.htm", "index.php"];
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NormalizeRule {
+    /// Uppercase percent-escapes and decode escaped unreserved characters
+    PercentCase,
+    /// Remove utm_*, gclid, fbclid and similar tracking parameters
+    StripTracking,
+    /// Sort query parameters by name
+    SortQuery,
+    /// Remove the trailing slash from non-root paths
+    TrailingSlash,
+    /// Collapse `/dir/index.html` to `/dir/`
+    IndexHtml,
+    /// Lowercase the path
+    LowercasePath,
+    /// Disable all rules
+    None,
+}
+
+/// Rewrites URLs into one canonical form so duplicates can be merged.
+#[derive(Debug, Clone, Default)]
+pub struct Normalizer {
+    rules: Vec<NormalizeRule>,
+}
+
+impl Normalizer {
+    pub fn from_config(config: &AppConfig) -> Self {
+        Self::new(&config.normalize)
+    }
+
+    pub fn new(rules: &[NormalizeRule]) -> Self {
+        if rules.contains(&NormalizeRule::None) {
+            return Self::default();
+        }
+        Self { rules: rules.to_vec() }
+    }
+
+    fn has(&self, rule: NormalizeRule) -> bool {
+        self.rules.contains(&rule)
+    }
+
+    pub fn normalize(&self, url: &Url) -> Url {
+        let mut url = url.clone();
+
+        let mut path = url.path().to_string();
+        if self.has(NormalizeRule::LowercasePath) {
+            path = path.to_lowercase();
+        }
+        if self.has(NormalizeRule::PercentCase) {
+            path = normalize_percent(&path);
+        }
+        if self.has(NormalizeRule::IndexHtml) {
+            if let Some(idx) = path.rfind('/') {
+                if INDEX_FILES.contains(&&path[idx + 1..]) {
+                    path.truncate(idx + 1);
+                }
+            }
+        }
+        if self.has(NormalizeRule::TrailingSlash) && path.len() > 1 {
+            let trimmed = path.trim_end_matches('/');
+            path = if trimmed.is_empty() { "/".to_string() } else { trimmed.to_string() };
+        }
+        if path != url.path() {
+            url.set_path(&path);
+        }
+
+        if let Some(query) = url.query() {
+            let mut params: Vec<String> = query
+                .split('&')
+                .filter(|p| !p.is_empty())
+                .map(|p| {
+                    if self.has(NormalizeRule::PercentCase) {
+                        normalize_percent(p)
+                    } else {
+                        p.to_string()
+                    }
+                })
+                .collect();
+
+            if self.has(NormalizeRule::StripTracking) {
+                params.retain(|p| !is_tracking_param(param_name(p)));
+            }
+            if self.has(NormalizeRule::SortQuery) {
+                params.sort_by(|a, b| param_name(a).cmp(param_name(b)));
+            }
+
+            let query = params.join("&");
+            if query.is_empty() {
+                url.set_query(None);
+            } else if query != url.query().unwrap_or_default() {
+                url.set_query(Some(&query));
+            }
+        }
+
+        url
+    }
+
+    /// Normalize a URL string, returning it unchanged if it does not parse.
+    pub fn normalize_str(&self, url: &str) -> String {
+        match Url::parse(url) {
+            Ok(parsed) => self.normalize(&parsed).into(),
+            Err(_) => url.to_string(),
+        }
+    }
+}
+
+fn param_name(param: &str) -> &str {
+    param.split('=').next().unwrap_or(param)
+}
+
+fn is_tracking_param(name: &str) -> bool {
+    let name = name.to_lowercase();
+    name.starts_with("utm_") || TRACKING_PARAMS.contains(&name.as_str())
+}
+
+/// `%7e` -> `~`, `%2f` -> `%2F`: decode unreserved characters, uppercase the rest.
+fn normalize_percent(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out: Vec<u8> = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'%'
+            && i + 2 < bytes.len()
+            && bytes[i + 1].is_ascii_hexdigit()
+            && bytes[i + 2].is_ascii_hexdigit()
+        {
+            let hex = [bytes[i + 1].to_ascii_uppercase(), bytes[i + 2].to_ascii_uppercase()];
+            let value = u8::from_str_radix(std::str::from_utf8(&hex).expect("ascii hex"), 16)
+                .expect("valid hex");
+            if value.is_ascii_alphanumeric() || matches!(value, b'-' | b'.' | b'_' | b'~') {
+                out.push(value);
+            } else {
+                out.push(b'%');
+                out.extend_from_slice(&hex);
+            }
+            i += 3;
+            continue;
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+
+    String::from_utf8(out).expect("only ASCII bytes were rewritten")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn normalize(rules: &[NormalizeRule], url: &str) -> String {
+        Normalizer::new(rules).normalize_str(url)
+    }
+
+    #[test]
+    fn percent_case_uppercases_escapes_and_decodes_unreserved() {
+        let rules = [NormalizeRule::PercentCase];
+        assert_eq!(normalize(&rules, "https://h/a%2fb%7euser"), "https://h/a%2Fb~user");
+        assert_eq!(normalize(&rules, "https://h/?q=%e2%82%ac&r=%41"), "https://h/?q=%E2%82%AC&r=A");
+        assert_eq!(normalize(&rules, "https://h/100%"), "https://h/100%");
+    }
+
+    #[test]
+    fn strip_tracking_removes_campaign_parameters_only() {
+        let rules = [NormalizeRule::StripTracking];
+        assert_eq!(normalize(&rules, "https://h/p?utm_source=x&id=3&UTM_Medium=y&gclid=z"), "https://h/p?id=3");
+        assert_eq!(normalize(&rules, "https://h/p?fbclid=1"), "https://h/p");
+        assert_eq!(normalize(&rules, "https://h/p?utmost=1"), "https://h/p?utmost=1");
+    }
+
+    #[test]
+    fn sort_query_orders_parameters_by_name() {
+        let rules = [NormalizeRule::SortQuery];
+        assert_eq!(normalize(&rules, "https://h/?b=2&a=1&c"), "https://h/?a=1&b=2&c");
+        // Repeated names keep their relative order.
+        assert_eq!(normalize(&rules, "https://h/?x=2&a=0&x=1"), "https://h/?a=0&x=2&x=1");
+    }
+
+    #[test]
+    fn trailing_slash_is_removed_except_at_the_root() {
+        let rules = [NormalizeRule::TrailingSlash];
+        assert_eq!(normalize(&rules, "https://h/docs/"), "https://h/docs");
+        assert_eq!(normalize(&rules, "https://h/docs//?page=2"), "https://h/docs?page=2");
+        assert_eq!(normalize(&rules, "https://h/"), "https://h/");
+    }
+
+    #[test]
+    fn index_html_collapses_to_the_directory() {
+        let rules = [NormalizeRule::IndexHtml];
+        assert_eq!(normalize(&rules, "https://h/dir/index.html"), "https://h/dir/");
+        assert_eq!(normalize(&rules, "https://h/index.php?x=1"), "https://h/?x=1");
+        assert_eq!(normalize(&rules, "https://h/dir/myindex.html"), "https://h/dir/myindex.html");
+    }
+
+    #[test]
+    fn lowercase_path_leaves_the_query_alone() {
+        let rules = [NormalizeRule::LowercasePath];
+        assert_eq!(normalize(&rules, "https://h/Docs/Page?Q=A"), "https://h/docs/page?Q=A");
+    }
+
+    #[test]
+    fn none_disables_every_rule() {
+        let url = "https://h/Dir/index.html?utm_source=x&b=1&a=2";
+        let rules = [NormalizeRule::LowercasePath, NormalizeRule::StripTracking, NormalizeRule::None];
+        assert_eq!(normalize(&rules, url), url);
+        assert_eq!(normalize(&[], url), url);
+    }
+
+    #[test]
+    fn default_ports_are_dropped_by_url_parsing() {
+        assert_eq!(normalize(&[], "http://h:80/"), "http://h/");
+        assert_eq!(normalize(&[], "https://h:443/"), "https://h/");
+        assert_eq!(normalize(&[], "HTTPS://H:443"), "https://h/");
+        assert_eq!(normalize(&[], "http://h:443/"), "http://h:443/");
+        assert_eq!(normalize(&[], "https://h:8443/"), "https://h:8443/");
+    }
+
+    #[test]
+    fn unparsable_urls_come_back_unchanged() {
+        assert_eq!(normalize(&[NormalizeRule::LowercasePath], "not a url"), "not a url");
+    }
+}
@@ -0,0 +1,167 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::mpsc;
+
+use crate::checker::CheckTarget;
+use crate::extractor::{Directive, ResourceKind};
+use crate::reconcile::coverage;
+use crate::sitemap_parser::SitemapEntry;
+use crate::visited::VisitedSet;
+
+/// Discovered URLs waiting for the checker. Discovery pauses when it is full,
+/// so a fast crawl cannot run arbitrarily far ahead of checking.
+pub const TARGET_QUEUE: usize = 1024;
+
+/// Checked statuses waiting to be written out. The checker pauses when it is full.
+pub const STATUS_QUEUE: usize = 1024;
+
+/// Requests that may be queued or running at once per `--concurrency` slot. The
+/// room past the limit lets the scheduler choose among many hosts, so one slow
+/// host does not hold up the rest.
+const IN_FLIGHT_PER_SLOT: usize = 4;
+
+/// How many crawl or check tasks may exist at once; further work waits in a queue.
+pub fn max_in_flight(concurrency: usize) -> usize {
+    concurrency.max(1) * IN_FLIGHT_PER_SLOT
+}
+
+/// Hands discovered URLs to the checker as soon as they are found, each URL
+/// only once per run no matter how many inputs lead to it. What a later
+/// duplicate adds is kept in `merges`, to be joined into the URL's row at the end.
+#[derive(Clone)]
+pub struct TargetSink {
+    tx: mpsc::Sender<CheckTarget>,
+    seen: Arc<dyn VisitedSet>,
+    queued: Queued,
+    merges: Merges,
+    /// Hybrid mode: the site's sitemap URLs, used to tag pages with their
+    /// coverage and give crawled pages their sitemap entry.
+    sitemap: Option<Arc<HashMap<String, Option<SitemapEntry>>>>,
+}
+
+impl TargetSink {
+    /// `seen` remembers the URLs already queued.
+    pub fn channel(seen: Arc<dyn VisitedSet>) -> (Self, mpsc::Receiver<CheckTarget>) {
+        let (tx, rx) = mpsc::channel(TARGET_QUEUE);
+        let sink = Self {
+            tx,
+            seen,
+            queued: Queued::default(),
+            merges: Merges::default(),
+            sitemap: None,
+        };
+        (sink, rx)
+    }
+
+    /// A sink for one hybrid crawl that tags every page it passes on as
+    /// `both`, `orphan` or `not-in-sitemap`.
+    pub fn reconciling(&self, sitemap: &[CheckTarget]) -> Self {
+        Self {
+            sitemap: Some(Arc::new(sitemap.iter().map(|t| (t.url.clone(), t.sitemap.clone())).collect())),
+            ..self.clone()
+        }
+    }
+
+    /// Queue `target` for checking unless its URL was queued before, in which
+    /// case only its aliases, directives, depth and sitemap entry are kept.
+    pub async fn send(&self, mut target: CheckTarget) {
+        if !self.seen.insert(&target.url) {
+            if !target.aliases.is_empty()
+                || !target.directives.is_empty()
+                || target.depth.is_some()
+                || target.sitemap.is_some()
+            {
+                let mut merges = self.merges.0.lock().expect("merges poisoned");
+                let merged = merges.entry(target.url).or_default();
+                merged.add_link(target.aliases, target.directives);
+                merged.depth = match (merged.depth, target.depth) {
+                    (Some(known), Some(depth)) => Some(known.min(depth)),
+                    (known, depth) => known.or(depth),
+                };
+                merged.sitemap = merged.sitemap.take().or(target.sitemap);
+            }
+            return;
+        }
+        if let (Some(sitemap), ResourceKind::Page) = (&self.sitemap, target.kind) {
+            let listed = sitemap.get(&target.url);
+            target.coverage = Some(coverage(listed.is_some(), target.depth.is_some()));
+            if target.sitemap.is_none() {
+                target.sitemap = listed.cloned().flatten();
+            }
+        }
+        self.queued.0.fetch_add(1, Ordering::Relaxed);
+        // Only fails once the checker is gone, and then there is nobody to tell.
+        let _ = self.tx.send(target).await;
+    }
+
+    /// What a link to `url` said about it after the crawler let go of its
+    /// record: another spelling and the link's directives.
+    pub fn add_link(&self, url: &str, alias: Option<String>, directives: Vec<Directive>) {
+        if alias.is_none() && directives.is_empty() {
+            return;
+        }
+        let mut merges = self.merges.0.lock().expect("merges poisoned");
+        merges.entry(url.to_string()).or_default().add_link(alias, directives);
+    }
+
+    /// What duplicates added to URLs already queued. Like `queued`, holding
+    /// it does not keep the checker waiting.
+    pub fn merges(&self) -> Merges {
+        self.merges.clone()
+    }
+
+    /// A live count of the URLs queued so far. Unlike a clone of the sink,
+    /// holding it does not keep the checker waiting for more.
+    pub fn queued(&self) -> Queued {
+        self.queued.clone()
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct Queued(Arc<AtomicUsize>);
+
+impl Queued {
+    pub fn get(&self) -> usize {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// What later duplicates of a queued URL said about it.
+#[derive(Debug, Default)]
+pub struct Merged {
+    pub aliases: Vec<String>,
+    pub directives: Vec<Directive>,
+    /// The shallowest click depth any of them was found at.
+    pub depth: Option<u32>,
+    /// The first sitemap entry among them.
+    pub sitemap: Option<SitemapEntry>,
+}
+
+impl Merged {
+    fn add_link(&mut self, aliases: impl IntoIterator<Item = String>, directives: Vec<Directive>) {
+        for alias in aliases {
+            if !self.aliases.contains(&alias) {
+                self.aliases.push(alias);
+            }
+        }
+        for directive in directives {
+            if !self.directives.contains(&directive) {
+                self.directives.push(directive);
+            }
+        }
+    }
+}
+
+/// Queued URL -> what its duplicates added. Only duplicates that bring
+/// something are kept, so this stays far smaller than the set of URLs.
+#[derive(Clone, Default)]
+pub struct Merges(Arc<Mutex<HashMap<String, Merged>>>);
+
+impl Merges {
+    /// Take what was merged into `url`, if anything.
+    pub fn take(&self, url: &str) -> Option<Merged> {
+        self.0.lock().expect("merges poisoned").remove(url)
+    }
+}
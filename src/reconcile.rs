@@ -0,0 +1,34 @@
+use std::fmt;
+
+/// Where a page was found in hybrid mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Coverage {
+    /// Listed in the sitemap and reachable by following links
+    Both,
+    /// Listed in the sitemap but not reachable from the start URL
+    Orphan,
+    /// Reachable by following links but missing from the sitemap
+    NotInSitemap,
+}
+
+impl fmt::Display for Coverage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Coverage::Both => "both",
+            Coverage::Orphan => "orphan",
+            Coverage::NotInSitemap => "not-in-sitemap",
+        };
+        f.pad(name)
+    }
+}
+
+/// Coverage of a page in hybrid mode. A crawled page counts as `linked` when it
+/// has a click depth, i.e. it was reached from the start URL rather than only
+/// from a sitemap seed.
+pub fn coverage(in_sitemap: bool, linked: bool) -> Coverage {
+    match (in_sitemap, linked) {
+        (true, true) => Coverage::Both,
+        (true, false) => Coverage::Orphan,
+        (false, _) => Coverage::NotInSitemap,
+    }
+}
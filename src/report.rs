@@ -0,0 +1,367 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use chrono::{DateTime, Local};
+use tracing::{info, warn};
+
+use crate::checker::UrlStatus;
+use crate::config::{AppConfig, Mode};
+use crate::crawler::LimitReached;
+use crate::discovery::DiscoveredSitemap;
+use crate::extractor::{Directive, ResourceKind};
+use crate::reconcile::Coverage;
+use crate::robots::BLOCKED_STATUS_TEXT;
+use crate::scheduler::{HostRate, CIRCUIT_OPEN_STATUS_TEXT};
+use crate::scope::CrawlScope;
+use crate::sitemap_parser::PartialSitemap;
+use crate::visited::VisitedSettings;
+
+/// Running status counters, updated as each result comes in.
+#[derive(Debug, Default, Clone)]
+pub struct Summary {
+    pub total: usize,
+    pub ok: usize,
+    pub redirect: usize,
+    pub client_err: usize,
+    pub server_err: usize,
+    pub connection_err: usize,
+    pub robots_blocked: usize,
+    /// Not checked because their host's circuit breaker was open.
+    pub circuit_open: usize,
+    /// Alternate spellings merged into checked URLs by normalization.
+    pub aliases_merged: usize,
+    /// Crawled URLs per click depth.
+    pub by_depth: BTreeMap<u32, usize>,
+    /// 4xx/5xx/connection failures per resource kind, so broken assets
+    /// can be told apart from broken pages.
+    pub broken_by_kind: BTreeMap<ResourceKind, usize>,
+    /// Checked URLs carrying each crawler directive.
+    pub by_directive: BTreeMap<Directive, usize>,
+    /// Hybrid mode: pages per sitemap/crawl coverage.
+    pub by_coverage: BTreeMap<Coverage, usize>,
+}
+
+impl Summary {
+    pub fn record(&mut self, status: &UrlStatus) {
+        self.total += 1;
+        self.aliases_merged += status.aliases.len();
+        if let Some(depth) = status.depth {
+            *self.by_depth.entry(depth).or_default() += 1;
+        }
+        if let Some(coverage) = status.coverage {
+            *self.by_coverage.entry(coverage).or_default() += 1;
+        }
+        for directive in &status.directives {
+            *self.by_directive.entry(*directive).or_default() += 1;
+        }
+
+        let broken = match status.status_code {
+            Some(code) => code >= 400,
+            None => status.status_text != BLOCKED_STATUS_TEXT,
+        };
+        if broken {
+            *self.broken_by_kind.entry(status.kind).or_default() += 1;
+        }
+
+        match status.status_code {
+            Some(200..=299) => self.ok += 1,
+            Some(300..=399) => self.redirect += 1,
+            Some(400..=499) => self.client_err += 1,
+            Some(500..=599) => self.server_err += 1,
+            Some(_) => {}
+            None if status.status_text == BLOCKED_STATUS_TEXT => self.robots_blocked += 1,
+            None if status.status_text == CIRCUIT_OPEN_STATUS_TEXT => self.circuit_open += 1,
+            None => self.connection_err += 1,
+        }
+    }
+
+    pub fn broken_pages(&self) -> usize {
+        self.broken_by_kind.get(&ResourceKind::Page).copied().unwrap_or_default()
+    }
+
+    pub fn broken_assets(&self) -> usize {
+        self.broken_by_kind
+            .iter()
+            .filter(|(kind, _)| **kind != ResourceKind::Page)
+            .map(|(_, count)| count)
+            .sum()
+    }
+}
+
+/// Everything worth knowing about a run once it is over: how it was configured,
+/// what was discovered along the way, and the final counts.
+pub struct RunReport {
+    started_at: DateTime<Local>,
+    mode: Mode,
+    visited: VisitedSettings,
+    inputs: Vec<String>,
+    scopes: Vec<(String, String)>,
+    sitemaps: Vec<DiscoveredSitemap>,
+    partial_sitemaps: Vec<PartialSitemap>,
+    limits_reached: Vec<(String, LimitReached)>,
+    excluded: usize,
+    nofollowed: usize,
+    links: usize,
+    host_rates: Vec<HostRate>,
+    circuits_opened: Vec<(String, usize)>,
+    pub summary: Summary,
+}
+
+impl RunReport {
+    pub fn new(config: &AppConfig) -> Self {
+        Self {
+            started_at: Local::now(),
+            mode: config.mode,
+            visited: VisitedSettings::from_config(config),
+            inputs: config.urls.clone(),
+            scopes: Vec::new(),
+            sitemaps: Vec::new(),
+            partial_sitemaps: Vec::new(),
+            limits_reached: Vec::new(),
+            excluded: 0,
+            nofollowed: 0,
+            links: 0,
+            host_rates: Vec::new(),
+            circuits_opened: Vec::new(),
+            summary: Summary::default(),
+        }
+    }
+
+    pub fn add_scope(&mut self, seed: &str, scope: &CrawlScope) {
+        self.scopes.push((seed.to_string(), scope.to_string()));
+    }
+
+    pub fn add_sitemaps(&mut self, sitemaps: &[DiscoveredSitemap]) {
+        self.sitemaps.extend_from_slice(sitemaps);
+    }
+
+    /// Sitemaps that could only be read in part, so their URLs may be incomplete.
+    pub fn add_partial_sitemaps(&mut self, partial: Vec<PartialSitemap>) {
+        self.partial_sitemaps.extend(partial);
+    }
+
+    pub fn add_limit_reached(&mut self, seed: &str, limit: LimitReached) {
+        self.limits_reached.push((seed.to_string(), limit));
+    }
+
+    /// Count URLs dropped by `--include`/`--exclude` before checking.
+    pub fn add_excluded(&mut self, count: usize) {
+        self.excluded += count;
+    }
+
+    /// Count links skipped because they were marked nofollow.
+    pub fn add_nofollowed(&mut self, count: usize) {
+        self.nofollowed += count;
+    }
+
+    /// Number of source -> target link edges recorded while crawling.
+    pub fn set_link_count(&mut self, links: usize) {
+        self.links = links;
+    }
+
+    /// Hosts that were slowed down by adaptive rate control.
+    pub fn set_host_rates(&mut self, rates: Vec<HostRate>) {
+        self.host_rates = rates;
+    }
+
+    /// Hosts whose circuit breaker opened, with how many times.
+    pub fn set_circuits_opened(&mut self, circuits: Vec<(String, usize)>) {
+        self.circuits_opened = circuits;
+    }
+
+    pub fn log(&self) {
+        for (seed, scope) in &self.scopes {
+            info!(url = %seed, scope = %scope, "Crawl scope");
+        }
+        for sitemap in &self.sitemaps {
+            info!(url = %sitemap.url, source = %sitemap.source, "Sitemap found");
+        }
+        for sitemap in &self.partial_sitemaps {
+            warn!(url = %sitemap.url, reason = %sitemap.reason, "Sitemap only partly parsed");
+        }
+        for (seed, limit) in &self.limits_reached {
+            info!(url = %seed, limit = %limit, "Crawl stopped early");
+        }
+        for (depth, count) in &self.summary.by_depth {
+            info!(depth, count, "URLs at depth");
+        }
+        for (kind, count) in &self.summary.broken_by_kind {
+            info!(kind = %kind, count, "Broken URLs by type");
+        }
+        for (directive, count) in &self.summary.by_directive {
+            info!(directive = %directive, count, "URLs with directive");
+        }
+        for (coverage, count) in &self.summary.by_coverage {
+            info!(coverage = %coverage, count, "Sitemap coverage");
+        }
+        for rate in &self.host_rates {
+            info!(
+                host = %rate.host,
+                backoffs = rate.backoffs,
+                lowest_concurrency = rate.lowest_concurrency,
+                longest_interval_ms = rate.longest_interval.as_millis() as u64,
+                concurrency = rate.concurrency,
+                interval_ms = rate.interval.as_millis() as u64,
+                "Host rate adjusted"
+            );
+        }
+        for (host, trips) in &self.circuits_opened {
+            info!(host = %host, times = trips, "Host circuit opened");
+        }
+
+        let s = &self.summary;
+        info!(
+            total = s.total,
+            ok_2xx = s.ok,
+            redirect_3xx = s.redirect,
+            client_err_4xx = s.client_err,
+            server_err_5xx = s.server_err,
+            connection_err = s.connection_err,
+            robots_blocked = s.robots_blocked,
+            circuit_open = s.circuit_open,
+            broken_pages = s.broken_pages(),
+            broken_assets = s.broken_assets(),
+            excluded_by_filter = self.excluded,
+            skipped_nofollow = self.nofollowed,
+            aliases_merged = s.aliases_merged,
+            links = self.links,
+            partial_sitemaps = self.partial_sitemaps.len(),
+            "Status check complete"
+        );
+    }
+
+    /// Write the report as plain text next to the CSV output.
+    pub fn write(&self, path: &Path) -> std::io::Result<()> {
+        let mut w = BufWriter::new(File::create(path)?);
+
+        writeln!(w, "sitemap-crawl run report")?;
+        writeln!(w)?;
+        writeln!(w, "Started:  {}", self.started_at.format("%Y-%m-%d %H:%M:%S"))?;
+        writeln!(w, "Finished: {}", Local::now().format("%Y-%m-%d %H:%M:%S"))?;
+        writeln!(w, "Mode:     {}", self.mode)?;
+        writeln!(w, "Visited:  {}", self.visited)?;
+
+        writeln!(w)?;
+        writeln!(w, "Inputs:")?;
+        for input in &self.inputs {
+            writeln!(w, "  {}", input)?;
+        }
+
+        if !self.scopes.is_empty() {
+            writeln!(w)?;
+            writeln!(w, "Crawl scope:")?;
+            for (seed, scope) in &self.scopes {
+                writeln!(w, "  {}: {}", seed, scope)?;
+            }
+        }
+
+        if !self.sitemaps.is_empty() {
+            writeln!(w)?;
+            writeln!(w, "Sitemaps found:")?;
+            for sitemap in &self.sitemaps {
+                writeln!(w, "  {} (from {})", sitemap.url, sitemap.source)?;
+            }
+        }
+
+        if !self.partial_sitemaps.is_empty() {
+            writeln!(w)?;
+            writeln!(w, "Sitemaps only partly parsed (URLs may be missing):")?;
+            for sitemap in &self.partial_sitemaps {
+                writeln!(w, "  {} ({})", sitemap.url, sitemap.reason)?;
+            }
+        }
+
+        if !self.limits_reached.is_empty() {
+            writeln!(w)?;
+            writeln!(w, "Crawls stopped early:")?;
+            for (seed, limit) in &self.limits_reached {
+                writeln!(w, "  {} ({})", seed, limit)?;
+            }
+        }
+
+        let s = &self.summary;
+        writeln!(w)?;
+        writeln!(w, "Summary:")?;
+        writeln!(w, "  Total:              {}", s.total)?;
+        writeln!(w, "  2xx OK:             {}", s.ok)?;
+        writeln!(w, "  3xx Redirect:       {}", s.redirect)?;
+        writeln!(w, "  4xx Client error:   {}", s.client_err)?;
+        writeln!(w, "  5xx Server error:   {}", s.server_err)?;
+        writeln!(w, "  Connection error:   {}", s.connection_err)?;
+        writeln!(w, "  Blocked by robots:  {}", s.robots_blocked)?;
+        writeln!(w, "  Host circuit open:  {}", s.circuit_open)?;
+        writeln!(w, "  Broken pages:       {}", s.broken_pages())?;
+        writeln!(w, "  Broken assets:      {}", s.broken_assets())?;
+        writeln!(w, "  Excluded by filter: {}", self.excluded)?;
+        writeln!(w, "  Skipped (nofollow): {}", self.nofollowed)?;
+        writeln!(w, "  Aliases merged:     {}", s.aliases_merged)?;
+        writeln!(w, "  Links recorded:     {}", self.links)?;
+        writeln!(w, "  Partial sitemaps:   {}", self.partial_sitemaps.len())?;
+
+        if !s.by_depth.is_empty() {
+            writeln!(w)?;
+            writeln!(w, "URLs by crawl depth:")?;
+            for (depth, count) in &s.by_depth {
+                writeln!(w, "  {:>3}: {}", depth, count)?;
+            }
+        }
+
+        if !s.broken_by_kind.is_empty() {
+            writeln!(w)?;
+            writeln!(w, "Broken URLs by type:")?;
+            for (kind, count) in &s.broken_by_kind {
+                writeln!(w, "  {:<10} {}", kind, count)?;
+            }
+        }
+
+        if !s.by_coverage.is_empty() {
+            writeln!(w)?;
+            writeln!(w, "Sitemap coverage:")?;
+            for (coverage, count) in &s.by_coverage {
+                writeln!(w, "  {:<15} {}", coverage, count)?;
+            }
+        }
+
+        if !self.host_rates.is_empty() {
+            writeln!(w)?;
+            writeln!(w, "Hosts slowed down by rate control:")?;
+            for rate in &self.host_rates {
+                writeln!(
+                    w,
+                    "  {}: {} backoffs, down to {} concurrent / {}ms apart, ended at {} / {}ms",
+                    rate.host,
+                    rate.backoffs,
+                    rate.lowest_concurrency,
+                    rate.longest_interval.as_millis(),
+                    rate.concurrency,
+                    rate.interval.as_millis(),
+                )?;
+            }
+        }
+
+        if !self.circuits_opened.is_empty() {
+            writeln!(w)?;
+            writeln!(w, "Host circuits opened:")?;
+            for (host, trips) in &self.circuits_opened {
+                writeln!(w, "  {}: {} times", host, trips)?;
+            }
+        }
+
+        if !s.by_directive.is_empty() {
+            writeln!(w)?;
+            writeln!(w, "URLs by directive:")?;
+            for (directive, count) in &s.by_directive {
+                writeln!(w, "  {:<15} {}", directive, count)?;
+            }
+        }
+
+        w.flush()
+    }
+}
+
+/// `result/sitemap_x.csv` + `report.txt` -> `result/sitemap_x.report.txt`
+pub fn output_path(csv_path: &str, extension: &str) -> std::path::PathBuf {
+    Path::new(csv_path).with_extension(extension)
+}
@@ -0,0 +1,199 @@
+use std::fmt;
+use std::time::Duration;
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use clap::ValueEnum;
+use rand::Rng;
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::StatusCode;
+
+use crate::config::AppConfig;
+
+/// Statuses retried unless `--retry-on` says otherwise.
+pub const DEFAULT_RETRY_STATUSES: [u16; 5] = [429, 500, 502, 503, 504];
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Jitter {
+    /// Exact exponential backoff
+    None,
+    /// A random wait between zero and the exponential backoff
+    Full,
+    /// A random wait between the base and three times the previous wait
+    Decorrelated,
+}
+
+impl fmt::Display for Jitter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = self.to_possible_value().expect("no skipped variants");
+        write!(f, "{}", value.get_name())
+    }
+}
+
+/// When and how long to wait before retrying a request.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    /// First backoff step, doubled on every retry.
+    pub base: Duration,
+    /// Longest wait before a retry, whether from backoff or from `Retry-After`.
+    pub max_wait: Duration,
+    pub jitter: Jitter,
+    /// Response statuses worth retrying.
+    pub statuses: Vec<u16>,
+}
+
+impl RetryPolicy {
+    pub fn from_config(config: &AppConfig) -> Self {
+        Self {
+            max_retries: config.retry,
+            base: Duration::from_millis(config.retry_base),
+            max_wait: Duration::from_secs(config.retry_max_wait),
+            jitter: config.retry_jitter,
+            statuses: config.retry_on.clone(),
+        }
+    }
+
+    pub fn retries_status(&self, status: StatusCode) -> bool {
+        self.statuses.contains(&status.as_u16())
+    }
+
+    /// Timeouts and connections that failed or dropped, including mid-body.
+    pub fn retries_error(&self, error: &reqwest::Error) -> bool {
+        error.is_timeout() || error.is_connect() || error.is_request() || error.is_body()
+    }
+
+    /// Wait before retry number `retry` (0-based); `previous` is the last wait, if any.
+    pub fn backoff(&self, retry: u32, previous: Option<Duration>) -> Duration {
+        let exponential = self.base.saturating_mul(2u32.saturating_pow(retry)).min(self.max_wait);
+        let wait = match self.jitter {
+            Jitter::None => exponential,
+            Jitter::Full => random_between(Duration::ZERO, exponential),
+            Jitter::Decorrelated => {
+                let previous = previous.unwrap_or(self.base);
+                random_between(self.base, previous.saturating_mul(3))
+            }
+        };
+        wait.min(self.max_wait)
+    }
+
+    /// The server's `Retry-After`, as seconds or an HTTP-date, capped at `max_wait`.
+    pub fn retry_after(&self, headers: &HeaderMap) -> Option<Duration> {
+        let value = headers.get(RETRY_AFTER)?.to_str().ok()?;
+        parse_retry_after(value, Utc::now()).map(|wait| wait.min(self.max_wait))
+    }
+}
+
+fn random_between(low: Duration, high: Duration) -> Duration {
+    if high <= low {
+        return low;
+    }
+    let millis = rand::rng().random_range(low.as_millis() as u64..=high.as_millis() as u64);
+    Duration::from_millis(millis)
+}
+
+/// `120` or `Sun, 06 Nov 1994 08:49:37 GMT`, plus the two obsolete date
+/// formats RFC 7231 still asks recipients to accept. A date in the past means
+/// no wait at all.
+fn parse_retry_after(value: &str, now: DateTime<Utc>) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+
+    let date = DateTime::parse_from_rfc2822(value)
+        .map(|d| d.with_timezone(&Utc))
+        .or_else(|_| {
+            // RFC 850: `Sunday, 06-Nov-94 08:49:37 GMT`; asctime: `Sun Nov  6 08:49:37 1994`
+            NaiveDateTime::parse_from_str(value, "%A, %d-%b-%y %H:%M:%S GMT")
+                .or_else(|_| NaiveDateTime::parse_from_str(value, "%a %b %e %H:%M:%S %Y"))
+                .map(|d| d.and_utc())
+        })
+        .ok()?;
+
+    Some((date - now).to_std().unwrap_or(Duration::ZERO))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn policy(jitter: Jitter) -> RetryPolicy {
+        RetryPolicy {
+            max_retries: 5,
+            base: Duration::from_millis(100),
+            max_wait: Duration::from_secs(1),
+            jitter,
+            statuses: DEFAULT_RETRY_STATUSES.to_vec(),
+        }
+    }
+
+    /// 37 seconds before the date in every example of RFC 7231.
+    fn now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(1994, 11, 6, 8, 49, 0).unwrap()
+    }
+
+    #[test]
+    fn retry_after_takes_seconds_and_all_three_date_forms() {
+        assert_eq!(parse_retry_after("120", now()), Some(Duration::from_secs(120)));
+        assert_eq!(parse_retry_after(" 0 ", now()), Some(Duration::ZERO));
+        let wait = Some(Duration::from_secs(37));
+        assert_eq!(parse_retry_after("Sun, 06 Nov 1994 08:49:37 GMT", now()), wait);
+        assert_eq!(parse_retry_after("Sunday, 06-Nov-94 08:49:37 GMT", now()), wait);
+        assert_eq!(parse_retry_after("Sun Nov  6 08:49:37 1994", now()), wait);
+    }
+
+    #[test]
+    fn retry_after_in_the_past_means_no_wait() {
+        assert_eq!(parse_retry_after("Sat, 05 Nov 1994 08:49:37 GMT", now()), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn retry_after_that_does_not_parse_is_ignored() {
+        for value in ["", "soon", "-5", "1.5", "2024-05-17T10:00:00Z"] {
+            assert_eq!(parse_retry_after(value, now()), None, "{}", value);
+        }
+    }
+
+    #[test]
+    fn retry_after_is_capped_at_max_wait() {
+        let policy = policy(Jitter::None);
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, "3600".parse().unwrap());
+        assert_eq!(policy.retry_after(&headers), Some(policy.max_wait));
+        headers.insert(RETRY_AFTER, "0".parse().unwrap());
+        assert_eq!(policy.retry_after(&headers), Some(Duration::ZERO));
+        assert_eq!(policy.retry_after(&HeaderMap::new()), None);
+    }
+
+    #[test]
+    fn plain_backoff_doubles_up_to_max_wait() {
+        let policy = policy(Jitter::None);
+        let waits: Vec<u128> = (0..6).map(|retry| policy.backoff(retry, None).as_millis()).collect();
+        assert_eq!(waits, [100, 200, 400, 800, 1000, 1000]);
+        assert_eq!(policy.backoff(u32::MAX, None), policy.max_wait);
+    }
+
+    #[test]
+    fn full_jitter_stays_under_the_exponential_step() {
+        let policy = policy(Jitter::Full);
+        for retry in 0..6 {
+            let step = policy.base.saturating_mul(1 << retry).min(policy.max_wait);
+            for _ in 0..100 {
+                assert!(policy.backoff(retry, None) <= step);
+            }
+        }
+    }
+
+    #[test]
+    fn decorrelated_jitter_stays_between_base_and_three_times_the_last_wait() {
+        let policy = policy(Jitter::Decorrelated);
+        let mut previous = None;
+        for retry in 0..50 {
+            let wait = policy.backoff(retry, previous);
+            let high = previous.unwrap_or(policy.base).saturating_mul(3).min(policy.max_wait);
+            assert!(wait >= policy.base && wait <= high, "{:?} not in {:?}..={:?}", wait, policy.base, high);
+            previous = Some(wait);
+        }
+    }
+}
@@ -0,0 +1,415 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use reqwest::Client;
+use tokio::sync::{Mutex, OnceCell};
+use tracing::{debug, info, warn};
+use url::Url;
+
+use crate::client::{get_body_with_retry, Pacing};
+use crate::retry::RetryPolicy;
+use crate::throttle::Throttle;
+
+/// Status text reported for URLs that robots.txt disallows.
+pub const BLOCKED_STATUS_TEXT: &str = "Blocked by robots.txt";
+
+#[derive(Debug, Clone)]
+struct Rule {
+    pattern: String,
+    allow: bool,
+}
+
+/// The rules of the robots.txt group that applies to our agent token.
+#[derive(Debug, Clone, Default)]
+pub struct RobotsRules {
+    rules: Vec<Rule>,
+    crawl_delay: Option<Duration>,
+    sitemaps: Vec<String>,
+}
+
+impl RobotsRules {
+    pub fn allow_all() -> Self {
+        Self::default()
+    }
+
+    pub fn disallow_all() -> Self {
+        Self {
+            rules: vec![Rule { pattern: "/".to_string(), allow: false }],
+            ..Self::default()
+        }
+    }
+
+    /// Parse a robots.txt body, keeping only the group(s) for `agent`,
+    /// or the `*` group when no group names the agent. Groups are matched on
+    /// the agent's product token (`sitemap-crawl` in `sitemap-crawl/1.0`),
+    /// exactly and regardless of case.
+    pub fn parse(body: &str, agent: &str) -> Self {
+        let agent = agent.to_lowercase();
+        let token = agent.split(['/', ' ']).next().unwrap_or_default();
+
+        let mut specific = RobotsRules::default();
+        let mut wildcard = RobotsRules::default();
+        let mut has_specific = false;
+
+        let mut group_agents: Vec<String> = Vec::new();
+        let mut in_rules = false;
+        let mut sitemaps = Vec::new();
+
+        for raw in body.lines() {
+            let line = raw.split('#').next().unwrap_or("").trim();
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            let key = key.trim().to_lowercase();
+            let value = value.trim();
+
+            // Sitemap lines are not tied to any group.
+            if key == "sitemap" {
+                if !value.is_empty() {
+                    sitemaps.push(value.to_string());
+                }
+                continue;
+            }
+
+            if key == "user-agent" {
+                if in_rules {
+                    group_agents.clear();
+                    in_rules = false;
+                }
+                group_agents.push(value.to_lowercase());
+                continue;
+            }
+
+            if group_agents.is_empty() {
+                continue;
+            }
+            in_rules = true;
+
+            let matches_agent = group_agents
+                .iter()
+                .any(|a| a != "*" && !a.is_empty() && a == token);
+            let matches_wildcard = group_agents.iter().any(|a| a == "*");
+
+            let target = if matches_agent {
+                has_specific = true;
+                &mut specific
+            } else if matches_wildcard {
+                &mut wildcard
+            } else {
+                continue;
+            };
+
+            match key.as_str() {
+                "allow" | "disallow" => {
+                    // An empty Disallow means "allow everything" and adds no rule.
+                    if value.is_empty() {
+                        continue;
+                    }
+                    target.rules.push(Rule {
+                        pattern: value.to_string(),
+                        allow: key == "allow",
+                    });
+                }
+                "crawl-delay" => {
+                    if let Ok(secs) = value.parse::<f64>() {
+                        if secs.is_finite() && secs >= 0.0 {
+                            target.crawl_delay = Some(Duration::from_secs_f64(secs));
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let mut rules = if has_specific { specific } else { wildcard };
+        rules.sitemaps = sitemaps;
+        rules
+    }
+
+    /// Longest matching rule wins; on a tie, Allow wins.
+    pub fn is_allowed(&self, url: &Url) -> bool {
+        let mut path = url.path().to_string();
+        if let Some(q) = url.query() {
+            path.push('?');
+            path.push_str(q);
+        }
+
+        let mut best: Option<(usize, bool)> = None;
+        for rule in &self.rules {
+            if !pattern_matches(&rule.pattern, &path) {
+                continue;
+            }
+            let len = rule.pattern.len();
+            best = match best {
+                Some((best_len, best_allow))
+                    if best_len > len || (best_len == len && best_allow) =>
+                {
+                    Some((best_len, best_allow))
+                }
+                _ => Some((len, rule.allow)),
+            };
+        }
+
+        best.map(|(_, allow)| allow).unwrap_or(true)
+    }
+
+    pub fn crawl_delay(&self) -> Option<Duration> {
+        self.crawl_delay
+    }
+
+    /// URLs from `Sitemap:` lines, in file order.
+    pub fn sitemaps(&self) -> &[String] {
+        &self.sitemaps
+    }
+}
+
+/// Match a robots.txt path pattern supporting `*` wildcards and a trailing `$` anchor.
+fn pattern_matches(pattern: &str, path: &str) -> bool {
+    let (pattern, anchored) = match pattern.strip_suffix('$') {
+        Some(p) => (p, true),
+        None => (pattern, false),
+    };
+
+    let parts: Vec<&str> = pattern.split('*').collect();
+    let mut pos = 0;
+
+    for (i, part) in parts.iter().enumerate() {
+        if i == 0 {
+            if !path.starts_with(part) {
+                return false;
+            }
+            pos = part.len();
+            continue;
+        }
+        if i == parts.len() - 1 && anchored {
+            return path.len() >= pos + part.len() && path.ends_with(part);
+        }
+        match path[pos..].find(part) {
+            Some(idx) => pos += idx + part.len(),
+            None => return false,
+        }
+    }
+
+    !anchored || pos == path.len()
+}
+
+struct HostRobots {
+    rules: RobotsRules,
+    next_slot: Mutex<Instant>,
+}
+
+/// Fetches and caches `/robots.txt` per origin, shared by the crawler and the checker.
+pub struct RobotsCache {
+    client: Client,
+    agent: String,
+    retry: RetryPolicy,
+    throttle: Arc<Throttle>,
+    hosts: Mutex<HashMap<String, Arc<OnceCell<Arc<HostRobots>>>>>,
+}
+
+impl RobotsCache {
+    pub fn new(client: Client, agent: &str, retry: RetryPolicy, throttle: Arc<Throttle>) -> Self {
+        Self {
+            client,
+            agent: agent.to_string(),
+            retry,
+            throttle,
+            hosts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub async fn rules(&self, url: &Url) -> RobotsRules {
+        self.host(url).await.rules.clone()
+    }
+
+    pub async fn is_allowed(&self, url: &Url) -> bool {
+        self.host(url).await.rules.is_allowed(url)
+    }
+
+    /// Sleep until this host's `Crawl-delay` allows another request.
+    pub async fn wait_turn(&self, url: &Url) {
+        let host = self.host(url).await;
+        let Some(delay) = host.rules.crawl_delay() else {
+            return;
+        };
+
+        let slot = {
+            let mut next = host.next_slot.lock().await;
+            let now = Instant::now();
+            let slot = (*next).max(now);
+            *next = slot + delay;
+            slot
+        };
+
+        tokio::time::sleep_until(slot.into()).await;
+    }
+
+    async fn host(&self, url: &Url) -> Arc<HostRobots> {
+        let origin = url.origin().ascii_serialization();
+
+        let cell = {
+            let mut hosts = self.hosts.lock().await;
+            Arc::clone(hosts.entry(origin.clone()).or_default())
+        };
+
+        cell.get_or_init(|| async {
+            let rules = fetch_robots(&self.client, &origin, &self.agent, &self.retry, &self.throttle).await;
+            Arc::new(HostRobots {
+                rules,
+                next_slot: Mutex::new(Instant::now()),
+            })
+        })
+        .await
+        .clone()
+    }
+}
+
+/// Fetch and parse `<origin>/robots.txt` for `agent`. Per RFC 9309, a
+/// robots.txt that is missing (4xx) allows everything, while one that cannot
+/// be reached (network error, 5xx or 429) disallows everything.
+pub async fn fetch_robots(
+    client: &Client,
+    origin: &str,
+    agent: &str,
+    retry: &RetryPolicy,
+    throttle: &Throttle,
+) -> RobotsRules {
+    let robots_url = format!("{}/robots.txt", origin);
+    let accept = |resp: &reqwest::Response| resp.status().is_success();
+
+    match get_body_with_retry(client, &robots_url, retry, Pacing::Throttle(throttle), accept).await.result {
+        Ok(fetched) => {
+            let status = fetched.status;
+            if let Some(body) = fetched.body {
+                let rules = RobotsRules::parse(&String::from_utf8_lossy(&body), agent);
+                info!(
+                    url = %robots_url,
+                    rules = rules.rules.len(),
+                    crawl_delay = ?rules.crawl_delay,
+                    sitemaps = rules.sitemaps.len(),
+                    "Loaded robots.txt"
+                );
+                rules
+            } else if status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+                warn!(url = %robots_url, status = %status, "robots.txt unreachable, disallowing all");
+                RobotsRules::disallow_all()
+            } else {
+                debug!(url = %robots_url, status = %status, "No robots.txt, allowing all");
+                RobotsRules::allow_all()
+            }
+        }
+        Err(e) => {
+            warn!(url = %robots_url, error = %e, "Failed to fetch robots.txt, disallowing all");
+            RobotsRules::disallow_all()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn allowed(rules: &RobotsRules, url: &str) -> bool {
+        rules.is_allowed(&Url::parse(url).unwrap())
+    }
+
+    #[test]
+    fn pattern_is_a_path_prefix() {
+        assert!(pattern_matches("/private", "/private"));
+        assert!(pattern_matches("/private", "/private/page.html"));
+        assert!(pattern_matches("/", "/anything"));
+        assert!(!pattern_matches("/private", "/public/private"));
+    }
+
+    #[test]
+    fn pattern_wildcards_match_any_run() {
+        assert!(pattern_matches("/*.pdf", "/docs/report.pdf"));
+        assert!(pattern_matches("/*?sort=", "/list?sort=asc"));
+        assert!(pattern_matches("/a*b*c", "/axxbyyc/rest"));
+        assert!(!pattern_matches("/a*b*c", "/axxc"));
+    }
+
+    #[test]
+    fn pattern_dollar_anchors_the_end() {
+        assert!(pattern_matches("/*.pdf$", "/docs/report.pdf"));
+        assert!(!pattern_matches("/*.pdf$", "/docs/report.pdf?download=1"));
+        assert!(pattern_matches("/exact$", "/exact"));
+        assert!(!pattern_matches("/exact$", "/exact/more"));
+    }
+
+    #[test]
+    fn longest_rule_wins_and_allow_wins_ties() {
+        let rules = RobotsRules::parse(
+            "User-agent: *\nDisallow: /shop\nAllow: /shop/public\nDisallow: /tie\nAllow: /tie\n",
+            "sitemap-crawl",
+        );
+        assert!(!allowed(&rules, "https://example.com/shop/cart"));
+        assert!(allowed(&rules, "https://example.com/shop/public/item"));
+        assert!(allowed(&rules, "https://example.com/tie"));
+        assert!(allowed(&rules, "https://example.com/elsewhere"));
+    }
+
+    #[test]
+    fn query_is_part_of_the_matched_path() {
+        let rules = RobotsRules::parse("User-agent: *\nDisallow: /*?session=\n", "sitemap-crawl");
+        assert!(!allowed(&rules, "https://example.com/page?session=1"));
+        assert!(allowed(&rules, "https://example.com/page?other=1"));
+    }
+
+    #[test]
+    fn specific_agent_group_replaces_wildcard_group() {
+        let body = "User-agent: *\nDisallow: /\n\nUser-agent: Sitemap-Crawl\nDisallow: /admin\nCrawl-delay: 2.5\n";
+        let rules = RobotsRules::parse(body, "sitemap-crawl/1.0");
+        assert!(allowed(&rules, "https://example.com/page"));
+        assert!(!allowed(&rules, "https://example.com/admin"));
+        assert_eq!(rules.crawl_delay(), Some(Duration::from_millis(2500)));
+
+        let other = RobotsRules::parse(body, "otherbot");
+        assert!(!allowed(&other, "https://example.com/page"));
+        assert_eq!(other.crawl_delay(), None);
+    }
+
+    #[test]
+    fn agent_groups_match_the_whole_product_token() {
+        let body = "User-agent: *\nDisallow: /all\n\nUser-agent: site\nDisallow: /site\n";
+        let rules = RobotsRules::parse(body, "sitemap-crawl");
+        assert!(!allowed(&rules, "https://example.com/all"));
+        assert!(allowed(&rules, "https://example.com/site"));
+
+        let body = "User-agent: SITEMAP-CRAWL\nDisallow: /mine\n";
+        let rules = RobotsRules::parse(body, "sitemap-crawl/2.1 (+https://example.com/bot)");
+        assert!(!allowed(&rules, "https://example.com/mine"));
+        let rules = RobotsRules::parse(body, "sitemap-crawler");
+        assert!(allowed(&rules, "https://example.com/mine"));
+    }
+
+    #[test]
+    fn consecutive_user_agents_share_a_group() {
+        let body = "User-agent: a\nUser-agent: sitemap-crawl\nDisallow: /x\nUser-agent: b\nDisallow: /y\n";
+        let rules = RobotsRules::parse(body, "sitemap-crawl");
+        assert!(!allowed(&rules, "https://example.com/x"));
+        assert!(allowed(&rules, "https://example.com/y"));
+    }
+
+    #[test]
+    fn empty_disallow_and_comments_add_no_rules() {
+        let body = "# comment\nUser-agent: *\nDisallow:\nDisallow: /tmp # trailing comment\n";
+        let rules = RobotsRules::parse(body, "sitemap-crawl");
+        assert!(allowed(&rules, "https://example.com/"));
+        assert!(!allowed(&rules, "https://example.com/tmp/file"));
+    }
+
+    #[test]
+    fn sitemaps_are_collected_outside_groups() {
+        let body = "Sitemap: https://example.com/a.xml\nUser-agent: *\nDisallow: /\nSitemap: https://example.com/b.xml\n";
+        let rules = RobotsRules::parse(body, "sitemap-crawl");
+        assert_eq!(rules.sitemaps(), ["https://example.com/a.xml", "https://example.com/b.xml"]);
+    }
+
+    #[test]
+    fn invalid_crawl_delay_is_ignored() {
+        let rules = RobotsRules::parse("User-agent: *\nCrawl-delay: soon\n", "sitemap-crawl");
+        assert_eq!(rules.crawl_delay(), None);
+    }
+}
@@ -0,0 +1,660 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use reqwest::StatusCode;
+use tokio::sync::oneshot;
+use tracing::{info, warn};
+use url::Url;
+
+use crate::config::AppConfig;
+use crate::throttle::Throttle;
+
+/// Healthy responses needed before a throttled host is sped up one step.
+const RECOVER_AFTER: usize = 20;
+/// Throttle signals this soon after a backoff come from requests already in
+/// flight and do not back off again.
+const BACKOFF_COOLDOWN: Duration = Duration::from_secs(2);
+/// Interval used once a host is down to one request at a time and still throttled.
+const MIN_BACKOFF_INTERVAL: Duration = Duration::from_millis(250);
+const MAX_BACKOFF_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Status text reported for URLs skipped because their host's circuit is open.
+pub const CIRCUIT_OPEN_STATUS_TEXT: &str = "Host circuit open";
+
+#[derive(Debug, Clone, Copy)]
+pub struct SchedulerLimits {
+    /// Requests in flight across all hosts.
+    pub global: usize,
+    /// Requests in flight to any one host.
+    pub per_host: usize,
+    /// Minimum time between the starts of two requests to the same host.
+    pub host_interval: Duration,
+    /// Consecutive failures that open a host's circuit; 0 disables the breaker.
+    pub circuit_threshold: usize,
+    /// How long an open circuit fails requests before letting a probe through.
+    pub circuit_cooldown: Duration,
+}
+
+impl SchedulerLimits {
+    pub fn from_config(config: &AppConfig) -> Self {
+        Self {
+            global: config.concurrency.max(1),
+            per_host: config.host_concurrency.max(1),
+            host_interval: Duration::from_millis(config.host_interval),
+            circuit_threshold: config.circuit_threshold,
+            circuit_cooldown: Duration::from_secs(config.circuit_cooldown),
+        }
+    }
+}
+
+/// What one request attempt says about the state of its host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Signal {
+    /// Any response other than the ones below
+    Healthy,
+    /// 429
+    RateLimited,
+    /// 503
+    Unavailable,
+    /// Any other 5xx
+    ServerError,
+    Timeout,
+    /// Connection refused, reset, DNS failure and the like
+    ConnectionError,
+    /// An error that says nothing about the host's health, such as too many
+    /// redirects or a body that does not decode
+    Other,
+}
+
+impl Signal {
+    pub fn from_status(status: StatusCode) -> Self {
+        match status {
+            StatusCode::TOO_MANY_REQUESTS => Signal::RateLimited,
+            StatusCode::SERVICE_UNAVAILABLE => Signal::Unavailable,
+            s if s.is_server_error() => Signal::ServerError,
+            _ => Signal::Healthy,
+        }
+    }
+
+    pub fn from_error(error: &reqwest::Error) -> Self {
+        if error.is_timeout() {
+            Signal::Timeout
+        } else if error.is_connect() {
+            Signal::ConnectionError
+        } else {
+            Signal::Other
+        }
+    }
+
+    /// The host is overloaded and wants us to slow down.
+    fn is_throttle(self) -> bool {
+        matches!(self, Signal::RateLimited | Signal::Unavailable | Signal::Timeout)
+    }
+
+    /// The host is down or broken; counts towards opening its circuit.
+    fn is_failure(self) -> bool {
+        matches!(
+            self,
+            Signal::Unavailable | Signal::ServerError | Signal::Timeout | Signal::ConnectionError
+        )
+    }
+}
+
+/// Returned instead of a permit while the host's circuit is open.
+#[derive(Debug, Clone, Copy)]
+pub struct CircuitOpen;
+
+#[derive(Debug, Clone, Copy)]
+enum Circuit {
+    Closed,
+    /// Requests fail fast until `until`; the next one after that is a probe.
+    Open { until: Instant },
+    /// A probe is in flight; its outcome closes or reopens the circuit.
+    HalfOpen,
+}
+
+struct Breaker {
+    circuit: Circuit,
+    failures: usize,
+    trips: usize,
+}
+
+impl Breaker {
+    fn rejects(&self, now: Instant) -> bool {
+        match self.circuit {
+            Circuit::Closed => false,
+            Circuit::Open { until } => now < until,
+            Circuit::HalfOpen => true,
+        }
+    }
+
+    fn record(&mut self, signal: Signal, host: &str, limits: &SchedulerLimits) {
+        if !signal.is_failure() {
+            self.failures = 0;
+            if !matches!(self.circuit, Circuit::Closed) {
+                self.circuit = Circuit::Closed;
+                info!(host, "Host is reachable again, closing circuit");
+            }
+            return;
+        }
+
+        self.failures += 1;
+        let trip = match self.circuit {
+            Circuit::Closed => limits.circuit_threshold > 0 && self.failures >= limits.circuit_threshold,
+            Circuit::HalfOpen => true,
+            Circuit::Open { .. } => false,
+        };
+        if trip {
+            self.circuit = Circuit::Open { until: Instant::now() + limits.circuit_cooldown };
+            self.trips += 1;
+            warn!(
+                host,
+                failures = self.failures,
+                cooldown_secs = limits.circuit_cooldown.as_secs(),
+                "Host keeps failing, opening circuit"
+            );
+        }
+    }
+}
+
+/// How the rate for one host was adjusted over the run.
+#[derive(Debug, Clone)]
+pub struct HostRate {
+    pub host: String,
+    pub backoffs: usize,
+    pub lowest_concurrency: usize,
+    pub longest_interval: Duration,
+    /// Where the rate stood when the run ended.
+    pub concurrency: usize,
+    pub interval: Duration,
+}
+
+struct HostQueue {
+    active: usize,
+    waiters: VecDeque<oneshot::Sender<Result<Permit, CircuitOpen>>>,
+    next_slot: Option<Instant>,
+    /// Current concurrency cap and interval, lowered on throttling and
+    /// raised back towards the configured limits when the host is healthy.
+    limit: usize,
+    interval: Duration,
+    healthy: usize,
+    last_backoff: Option<Instant>,
+    backoffs: usize,
+    lowest_limit: usize,
+    longest_interval: Duration,
+    breaker: Breaker,
+}
+
+impl HostQueue {
+    fn new(limits: &SchedulerLimits) -> Self {
+        Self {
+            active: 0,
+            waiters: VecDeque::new(),
+            next_slot: None,
+            limit: limits.per_host,
+            interval: limits.host_interval,
+            healthy: 0,
+            last_backoff: None,
+            backoffs: 0,
+            lowest_limit: limits.per_host,
+            longest_interval: limits.host_interval,
+            breaker: Breaker {
+                circuit: Circuit::Closed,
+                failures: 0,
+                trips: 0,
+            },
+        }
+    }
+
+    fn fail_waiters(&mut self) {
+        for waiter in self.waiters.drain(..) {
+            let _ = waiter.send(Err(CircuitOpen));
+        }
+    }
+
+    /// Halve the concurrency; once it is down to one, double the interval instead.
+    fn back_off(&mut self, host: &str) {
+        let now = Instant::now();
+        self.healthy = 0;
+        if self.last_backoff.is_some_and(|t| now < t + BACKOFF_COOLDOWN) {
+            return;
+        }
+        self.last_backoff = Some(now);
+
+        if self.limit > 1 {
+            self.limit /= 2;
+        } else {
+            self.interval = (self.interval * 2).clamp(MIN_BACKOFF_INTERVAL, MAX_BACKOFF_INTERVAL);
+        }
+        self.backoffs += 1;
+        self.lowest_limit = self.lowest_limit.min(self.limit);
+        self.longest_interval = self.longest_interval.max(self.interval);
+        warn!(
+            host,
+            concurrency = self.limit,
+            interval_ms = self.interval.as_millis() as u64,
+            "Host is throttling, slowing down"
+        );
+    }
+
+    /// Undo one backoff step, interval first, never past the configured limits.
+    fn recover(&mut self, host: &str, limits: &SchedulerLimits) {
+        self.healthy += 1;
+        if self.healthy < RECOVER_AFTER {
+            return;
+        }
+        self.healthy = 0;
+
+        if self.interval > limits.host_interval {
+            let halved = self.interval / 2;
+            self.interval = if halved < MIN_BACKOFF_INTERVAL {
+                limits.host_interval
+            } else {
+                halved.max(limits.host_interval)
+            };
+        } else if self.limit < limits.per_host {
+            self.limit += 1;
+        } else {
+            return;
+        }
+        info!(
+            host,
+            concurrency = self.limit,
+            interval_ms = self.interval.as_millis() as u64,
+            "Host is healthy, speeding up"
+        );
+    }
+}
+
+struct Inner {
+    limits: SchedulerLimits,
+    throttle: Arc<Throttle>,
+    active: usize,
+    hosts: HashMap<String, HostQueue>,
+    /// Hosts with waiters, in the order they get their next turn.
+    ready: VecDeque<String>,
+}
+
+impl Inner {
+    /// Hand out permits while there is room, one host at a time in round-robin order.
+    /// Waiters for a host whose circuit is open fail right away, without a slot.
+    fn dispatch(&mut self, shared: &Arc<Mutex<Inner>>) {
+        let now = Instant::now();
+        let hosts = &mut self.hosts;
+        self.ready.retain(|host| {
+            let queue = hosts.get_mut(host).expect("ready host has a queue");
+            if queue.breaker.rejects(now) {
+                queue.fail_waiters();
+            }
+            !queue.waiters.is_empty()
+        });
+
+        let mut idle_turns = 0;
+        while self.active < self.limits.global && idle_turns < self.ready.len() {
+            let host = self.ready.pop_front().expect("ready is not empty");
+            let queue = self.hosts.get_mut(&host).expect("ready host has a queue");
+
+            if queue.breaker.rejects(now) {
+                queue.fail_waiters();
+                continue;
+            }
+
+            if queue.active >= queue.limit {
+                self.ready.push_back(host);
+                idle_turns += 1;
+                continue;
+            }
+            idle_turns = 0;
+
+            let Some(waiter) = queue.waiters.pop_front() else {
+                continue;
+            };
+            let not_before = queue.next_slot.map_or(now, |slot| slot.max(now));
+            queue.next_slot = Some(not_before + queue.interval);
+
+            // An open circuit whose cooldown is over lets this one request through as a probe.
+            let probe = matches!(queue.breaker.circuit, Circuit::Open { .. });
+            let permit = Permit {
+                shared: Some(Arc::clone(shared)),
+                host: host.clone(),
+                not_before,
+                probe,
+                throttle: Arc::clone(&self.throttle),
+            };
+            match waiter.send(Ok(permit)) {
+                Ok(()) => {
+                    queue.active += 1;
+                    self.active += 1;
+                    if probe {
+                        queue.breaker.circuit = Circuit::HalfOpen;
+                    }
+                }
+                // The request was abandoned; releasing here would re-lock the scheduler.
+                Err(result) => {
+                    if let Ok(mut permit) = result {
+                        permit.shared = None;
+                    }
+                }
+            }
+
+            if !queue.waiters.is_empty() {
+                self.ready.push_back(host);
+            }
+        }
+    }
+}
+
+/// Shared by the crawler and the checker so no host gets more than its share:
+/// each host has its own concurrency cap and minimum request interval, hosts
+/// take turns for free slots, and the global limit caps everything. Every
+/// permit also carries the run-wide request rate and bandwidth throttle.
+#[derive(Clone)]
+pub struct HostScheduler {
+    shared: Arc<Mutex<Inner>>,
+}
+
+impl HostScheduler {
+    pub fn new(limits: SchedulerLimits, throttle: Arc<Throttle>) -> Self {
+        Self {
+            shared: Arc::new(Mutex::new(Inner {
+                limits,
+                throttle,
+                active: 0,
+                hosts: HashMap::new(),
+                ready: VecDeque::new(),
+            })),
+        }
+    }
+
+    /// Wait for a slot to request `url`. The slot is held until the permit is dropped.
+    /// Fails at once, or as soon as it opens, while the host's circuit is open.
+    pub async fn acquire(&self, url: &str) -> Result<Permit, CircuitOpen> {
+        let host = Url::parse(url)
+            .ok()
+            .and_then(|u| u.host_str().map(str::to_lowercase))
+            .unwrap_or_default();
+
+        let (tx, rx) = oneshot::channel();
+        {
+            let mut inner = self.shared.lock().expect("scheduler poisoned");
+            let limits = inner.limits;
+            let queue = inner.hosts.entry(host.clone()).or_insert_with(|| HostQueue::new(&limits));
+            queue.waiters.push_back(tx);
+            if queue.waiters.len() == 1 {
+                inner.ready.push_back(host);
+            }
+            inner.dispatch(&self.shared);
+        }
+
+        let permit = rx.await.expect("scheduler dropped a waiter")?;
+        tokio::time::sleep_until(permit.not_before.into()).await;
+        Ok(permit)
+    }
+
+    /// Hosts whose rate had to be lowered at some point, by name.
+    pub fn rate_summary(&self) -> Vec<HostRate> {
+        let inner = self.shared.lock().expect("scheduler poisoned");
+        let mut rates: Vec<HostRate> = inner
+            .hosts
+            .iter()
+            .filter(|(_, queue)| queue.backoffs > 0)
+            .map(|(host, queue)| HostRate {
+                host: host.clone(),
+                backoffs: queue.backoffs,
+                lowest_concurrency: queue.lowest_limit,
+                longest_interval: queue.longest_interval,
+                concurrency: queue.limit,
+                interval: queue.interval,
+            })
+            .collect();
+        rates.sort_by(|a, b| a.host.cmp(&b.host));
+        rates
+    }
+
+    /// Hosts whose circuit opened at some point, with how many times it did.
+    pub fn circuit_summary(&self) -> Vec<(String, usize)> {
+        let inner = self.shared.lock().expect("scheduler poisoned");
+        let mut trips: Vec<(String, usize)> = inner
+            .hosts
+            .iter()
+            .filter(|(_, queue)| queue.breaker.trips > 0)
+            .map(|(host, queue)| (host.clone(), queue.breaker.trips))
+            .collect();
+        trips.sort();
+        trips
+    }
+}
+
+/// A request slot for one host; frees the slot for the next waiter when dropped.
+pub struct Permit {
+    shared: Option<Arc<Mutex<Inner>>>,
+    host: String,
+    not_before: Instant,
+    /// This request decides whether an open circuit closes again.
+    probe: bool,
+    throttle: Arc<Throttle>,
+}
+
+impl Permit {
+    pub fn throttle(&self) -> &Throttle {
+        &self.throttle
+    }
+
+    /// Feed the outcome of one request attempt into the host's rate control and circuit breaker.
+    pub fn record(&self, signal: Signal) {
+        let Some(shared) = &self.shared else {
+            return;
+        };
+        if signal == Signal::Other {
+            return;
+        }
+        let mut inner = shared.lock().expect("scheduler poisoned");
+        let limits = inner.limits;
+        let Some(queue) = inner.hosts.get_mut(&self.host) else {
+            return;
+        };
+        if signal.is_throttle() {
+            queue.back_off(&self.host);
+        } else if signal == Signal::Healthy {
+            queue.recover(&self.host, &limits);
+        }
+        queue.breaker.record(signal, &self.host, &limits);
+        // A raised limit may let waiters through, and an opened circuit fails them.
+        inner.dispatch(shared);
+    }
+
+    /// Whether the host's circuit opened, so retrying this request is pointless.
+    pub fn circuit_open(&self) -> bool {
+        let Some(shared) = &self.shared else {
+            return false;
+        };
+        let inner = shared.lock().expect("scheduler poisoned");
+        inner
+            .hosts
+            .get(&self.host)
+            .is_some_and(|queue| matches!(queue.breaker.circuit, Circuit::Open { .. }))
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let Some(shared) = self.shared.take() else {
+            return;
+        };
+        let mut inner = shared.lock().expect("scheduler poisoned");
+        inner.active -= 1;
+        if let Some(queue) = inner.hosts.get_mut(&self.host) {
+            queue.active -= 1;
+            // A probe that ended without a response leaves the next request to probe.
+            if self.probe && matches!(queue.breaker.circuit, Circuit::HalfOpen) {
+                queue.breaker.circuit = Circuit::Open { until: Instant::now() };
+            }
+        }
+        inner.dispatch(&shared);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits(global: usize, per_host: usize) -> SchedulerLimits {
+        SchedulerLimits {
+            global,
+            per_host,
+            host_interval: Duration::ZERO,
+            circuit_threshold: 2,
+            circuit_cooldown: Duration::from_millis(50),
+        }
+    }
+
+    fn scheduler(limits: SchedulerLimits) -> HostScheduler {
+        HostScheduler::new(limits, Arc::default())
+    }
+
+    /// Requests queued across all hosts.
+    fn waiting(scheduler: &HostScheduler) -> usize {
+        let inner = scheduler.shared.lock().unwrap();
+        inner.hosts.values().map(|queue| queue.waiters.len()).sum()
+    }
+
+    fn circuit(scheduler: &HostScheduler, host: &str) -> Circuit {
+        scheduler.shared.lock().unwrap().hosts[host].breaker.circuit
+    }
+
+    /// Queue a request that notes its name once it gets a slot and frees it at once.
+    async fn queue_request(scheduler: &HostScheduler, url: &'static str, order: &Arc<Mutex<Vec<&'static str>>>) {
+        let before = waiting(scheduler);
+        let (queued, order) = (scheduler.clone(), Arc::clone(order));
+        tokio::spawn(async move {
+            let _permit = queued.acquire(url).await.unwrap();
+            order.lock().unwrap().push(url);
+        });
+        while waiting(scheduler) == before {
+            tokio::task::yield_now().await;
+        }
+    }
+
+    #[tokio::test]
+    async fn hosts_take_turns_for_free_slots() {
+        let scheduler = scheduler(limits(1, 4));
+        let held = scheduler.acquire("https://a.test/0").await.unwrap();
+        let order = Arc::new(Mutex::new(Vec::new()));
+        queue_request(&scheduler, "https://a.test/1", &order).await;
+        queue_request(&scheduler, "https://a.test/2", &order).await;
+        queue_request(&scheduler, "https://b.test/1", &order).await;
+
+        drop(held);
+        while order.lock().unwrap().len() < 3 {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(*order.lock().unwrap(), ["https://a.test/1", "https://b.test/1", "https://a.test/2"]);
+    }
+
+    #[tokio::test]
+    async fn each_host_is_capped_on_its_own() {
+        let scheduler = scheduler(limits(10, 2));
+        let first = scheduler.acquire("https://a.test/1").await.unwrap();
+        let _second = scheduler.acquire("https://a.test/2").await.unwrap();
+
+        let third = tokio::spawn({
+            let scheduler = scheduler.clone();
+            async move { scheduler.acquire("https://a.test/3").await.is_ok() }
+        });
+        let wait = Duration::from_millis(50);
+        assert!(tokio::time::timeout(wait, scheduler.acquire("https://b.test/1")).await.is_ok());
+        tokio::time::sleep(wait).await;
+        assert!(!third.is_finished());
+
+        drop(first);
+        assert!(third.await.unwrap());
+    }
+
+    #[test]
+    fn backs_off_and_recovers_step_by_step() {
+        let limits = SchedulerLimits { per_host: 4, ..limits(10, 4) };
+        let mut queue = HostQueue::new(&limits);
+
+        queue.back_off("a.test");
+        assert_eq!((queue.limit, queue.interval), (2, Duration::ZERO));
+        // Signals from requests already in flight do not back off again.
+        queue.back_off("a.test");
+        assert_eq!(queue.limit, 2);
+
+        queue.last_backoff = None;
+        queue.back_off("a.test");
+        queue.last_backoff = None;
+        queue.back_off("a.test");
+        assert_eq!((queue.limit, queue.interval), (1, MIN_BACKOFF_INTERVAL));
+        assert_eq!(queue.backoffs, 3);
+
+        for _ in 1..RECOVER_AFTER {
+            queue.recover("a.test", &limits);
+        }
+        assert_eq!(queue.interval, MIN_BACKOFF_INTERVAL);
+        // The interval is undone before the concurrency.
+        queue.recover("a.test", &limits);
+        assert_eq!((queue.limit, queue.interval), (1, Duration::ZERO));
+
+        for _ in 0..RECOVER_AFTER * 10 {
+            queue.recover("a.test", &limits);
+        }
+        assert_eq!(queue.limit, limits.per_host);
+        assert_eq!((queue.lowest_limit, queue.longest_interval), (1, MIN_BACKOFF_INTERVAL));
+    }
+
+    #[tokio::test]
+    async fn circuit_opens_probes_and_closes() {
+        let scheduler = scheduler(limits(10, 4));
+        let permit = scheduler.acquire("https://a.test/1").await.unwrap();
+        permit.record(Signal::ServerError);
+        assert!(matches!(circuit(&scheduler, "a.test"), Circuit::Closed));
+        permit.record(Signal::ConnectionError);
+        assert!(matches!(circuit(&scheduler, "a.test"), Circuit::Open { .. }));
+        assert!(permit.circuit_open());
+        drop(permit);
+
+        // Open: fail fast until the cooldown is over.
+        assert!(scheduler.acquire("https://a.test/2").await.is_err());
+        assert!(scheduler.acquire("https://b.test/1").await.is_ok());
+        tokio::time::sleep(Duration::from_millis(60)).await;
+
+        // The first request after the cooldown probes; others fail while it runs.
+        let probe = scheduler.acquire("https://a.test/3").await.unwrap();
+        assert!(matches!(circuit(&scheduler, "a.test"), Circuit::HalfOpen));
+        assert!(scheduler.acquire("https://a.test/4").await.is_err());
+
+        // A failed probe reopens the circuit straight away.
+        probe.record(Signal::Timeout);
+        assert!(matches!(circuit(&scheduler, "a.test"), Circuit::Open { .. }));
+        drop(probe);
+        tokio::time::sleep(Duration::from_millis(60)).await;
+
+        let probe = scheduler.acquire("https://a.test/5").await.unwrap();
+        probe.record(Signal::Healthy);
+        assert!(matches!(circuit(&scheduler, "a.test"), Circuit::Closed));
+        drop(probe);
+        assert!(scheduler.acquire("https://a.test/6").await.is_ok());
+        assert_eq!(scheduler.circuit_summary(), [("a.test".to_string(), 2)]);
+    }
+
+    #[tokio::test]
+    async fn only_connect_errors_and_timeouts_count_against_the_host() {
+        let client = reqwest::Client::new();
+        let refused = client.get("http://127.0.0.1:1/").send().await.unwrap_err();
+        assert_eq!(Signal::from_error(&refused), Signal::ConnectionError);
+        let invalid = client.get("http://exa mple.com/").send().await.unwrap_err();
+        assert_eq!(Signal::from_error(&invalid), Signal::Other);
+    }
+
+    #[tokio::test]
+    async fn errors_that_say_nothing_about_the_host_are_ignored() {
+        let scheduler = scheduler(limits(10, 4));
+        let permit = scheduler.acquire("https://a.test/1").await.unwrap();
+        for _ in 0..5 {
+            permit.record(Signal::Other);
+        }
+        assert!(matches!(circuit(&scheduler, "a.test"), Circuit::Closed));
+    }
+}
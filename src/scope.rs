@@ -0,0 +1,222 @@
+use std::fmt;
+
+use clap::ValueEnum;
+use url::{Host, Url};
+
+use crate::config::AppConfig;
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScopePolicy {
+    /// Only the exact host of the start URL
+    Host,
+    /// The registrable domain of the start URL and all of its subdomains
+    Domain,
+    /// The start URL's host plus every `--allow-host`
+    Hosts,
+}
+
+impl fmt::Display for ScopePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = self.to_possible_value().expect("no skipped variants");
+        write!(f, "{}", value.get_name())
+    }
+}
+
+/// Scope options as given on the command line, before a seed is known.
+#[derive(Debug, Clone)]
+pub struct ScopeSettings {
+    pub policy: ScopePolicy,
+    pub allowed_hosts: Vec<String>,
+    pub strict_scheme: bool,
+    /// Check images, scripts and other assets on any host, not just in-scope ones.
+    pub external_resources: bool,
+}
+
+impl ScopeSettings {
+    pub fn from_config(config: &AppConfig) -> Self {
+        Self {
+            policy: config.scope,
+            allowed_hosts: config
+                .allow_host
+                .iter()
+                .map(|h| h.trim().to_lowercase())
+                .collect(),
+            strict_scheme: config.strict_scheme,
+            external_resources: config.external_resources,
+        }
+    }
+}
+
+/// Decides which discovered links belong to a crawl.
+#[derive(Debug, Clone)]
+pub struct CrawlScope {
+    settings: ScopeSettings,
+    seed_scheme: String,
+    seed_host: String,
+    seed_domain: String,
+}
+
+impl CrawlScope {
+    pub fn new(seed: &Url, settings: &ScopeSettings) -> Self {
+        let seed_host = seed.host_str().unwrap_or_default().to_lowercase();
+        let seed_domain = registrable_domain(seed);
+
+        Self {
+            settings: settings.clone(),
+            seed_scheme: seed.scheme().to_string(),
+            seed_host,
+            seed_domain,
+        }
+    }
+
+    pub fn contains(&self, url: &Url) -> bool {
+        let scheme = url.scheme();
+        if scheme != "http" && scheme != "https" {
+            return false;
+        }
+        if self.settings.strict_scheme && scheme != self.seed_scheme {
+            return false;
+        }
+
+        let Some(host) = url.host_str() else {
+            return false;
+        };
+        let host = host.to_lowercase();
+
+        match self.settings.policy {
+            ScopePolicy::Host => host == self.seed_host,
+            ScopePolicy::Domain => registrable_domain(url) == self.seed_domain,
+            ScopePolicy::Hosts => {
+                host == self.seed_host
+                    || self
+                        .settings
+                        .allowed_hosts
+                        .iter()
+                        .any(|allowed| host_matches(allowed, &host))
+            }
+        }
+    }
+
+    /// Whether an asset (image, script, ...) referenced by an in-scope page should be checked.
+    pub fn contains_resource(&self, url: &Url) -> bool {
+        if self.settings.external_resources {
+            matches!(url.scheme(), "http" | "https") && url.host_str().is_some()
+        } else {
+            self.contains(url)
+        }
+    }
+}
+
+impl fmt::Display for CrawlScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.settings.policy {
+            ScopePolicy::Host => write!(f, "host {}", self.seed_host)?,
+            ScopePolicy::Domain => write!(f, "domain {} and subdomains", self.seed_domain)?,
+            ScopePolicy::Hosts => {
+                write!(f, "hosts {}", self.seed_host)?;
+                for host in &self.settings.allowed_hosts {
+                    write!(f, ", {}", host)?;
+                }
+            }
+        }
+        if self.settings.strict_scheme {
+            write!(f, " ({} only)", self.seed_scheme)?;
+        } else {
+            write!(f, " (http and https)")?;
+        }
+        if self.settings.external_resources {
+            write!(f, ", assets on any host")?;
+        }
+        Ok(())
+    }
+}
+
+/// `*.example.com` matches any subdomain of example.com; anything else must match exactly.
+fn host_matches(pattern: &str, host: &str) -> bool {
+    match pattern.strip_prefix("*.") {
+        Some(suffix) => host.len() > suffix.len() && host.ends_with(&format!(".{}", suffix)),
+        None => pattern == host,
+    }
+}
+
+/// `blog.example.co.uk` -> `example.co.uk`. IPs and unknown suffixes fall back to the host itself.
+fn registrable_domain(url: &Url) -> String {
+    match url.host() {
+        Some(Host::Domain(domain)) => {
+            let domain = domain.to_lowercase();
+            psl::domain_str(&domain).unwrap_or(&domain).to_string()
+        }
+        // The public suffix list would read `10.0.0.1` as a domain under `1`.
+        _ => url.host_str().unwrap_or_default().to_lowercase(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn crawl_scope(seed: &str, policy: ScopePolicy, allowed_hosts: &[&str], strict_scheme: bool) -> CrawlScope {
+        let settings = ScopeSettings {
+            policy,
+            allowed_hosts: allowed_hosts.iter().map(|h| h.to_string()).collect(),
+            strict_scheme,
+            external_resources: false,
+        };
+        CrawlScope::new(&Url::parse(seed).unwrap(), &settings)
+    }
+
+    fn contains(scope: &CrawlScope, url: &str) -> bool {
+        scope.contains(&Url::parse(url).unwrap())
+    }
+
+    #[test]
+    fn host_policy_keeps_to_the_seed_host() {
+        let scope = crawl_scope("https://www.example.com/", ScopePolicy::Host, &[], false);
+        assert!(contains(&scope, "https://www.example.com/about"));
+        assert!(contains(&scope, "https://WWW.Example.com/about"));
+        assert!(!contains(&scope, "https://example.com/"));
+        assert!(!contains(&scope, "https://blog.example.com/"));
+        assert!(!contains(&scope, "ftp://www.example.com/file"));
+    }
+
+    #[test]
+    fn domain_policy_includes_subdomains_of_the_registrable_domain() {
+        let scope = crawl_scope("https://www.example.co.uk/", ScopePolicy::Domain, &[], false);
+        assert!(contains(&scope, "https://example.co.uk/"));
+        assert!(contains(&scope, "https://blog.shop.example.co.uk/"));
+        assert!(!contains(&scope, "https://other.co.uk/"));
+        assert!(!contains(&scope, "https://example.com/"));
+    }
+
+    #[test]
+    fn domain_policy_keeps_ip_seeds_to_the_exact_address() {
+        let scope = crawl_scope("http://10.0.0.1/", ScopePolicy::Domain, &[], false);
+        assert!(contains(&scope, "http://10.0.0.1:8080/page"));
+        assert!(!contains(&scope, "http://127.0.0.1/"));
+        assert!(!contains(&scope, "http://192.168.0.1/"));
+
+        let scope = crawl_scope("http://[::1]/", ScopePolicy::Domain, &[], false);
+        assert!(contains(&scope, "http://[::1]/page"));
+        assert!(!contains(&scope, "http://[::2]/"));
+    }
+
+    #[test]
+    fn hosts_policy_adds_the_allowed_hosts() {
+        let scope = crawl_scope("https://example.com/", ScopePolicy::Hosts, &["cdn.example.net", "*.example.org"], false);
+        assert!(contains(&scope, "https://example.com/"));
+        assert!(contains(&scope, "https://cdn.example.net/app.js"));
+        assert!(contains(&scope, "https://docs.example.org/"));
+        assert!(!contains(&scope, "https://example.org/"));
+        assert!(!contains(&scope, "https://www.example.com/"));
+    }
+
+    #[test]
+    fn strict_scheme_rejects_the_other_scheme() {
+        let lenient = crawl_scope("https://example.com/", ScopePolicy::Host, &[], false);
+        assert!(contains(&lenient, "http://example.com/"));
+
+        let strict = crawl_scope("https://example.com/", ScopePolicy::Host, &[], true);
+        assert!(contains(&strict, "https://example.com/"));
+        assert!(!contains(&strict, "http://example.com/"));
+    }
+}
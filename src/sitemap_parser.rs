@@ -1,133 +1,999 @@
-use flate2::read::GzDecoder;
+use std::collections::{HashSet, VecDeque};
+use std::fmt;
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use async_compression::tokio::bufread::GzipDecoder;
+use chrono::{DateTime, FixedOffset, NaiveDate};
+use futures::stream::{FuturesUnordered, StreamExt, TryStreamExt};
+use quick_xml::errors::IllFormedError;
 use quick_xml::events::Event;
-use quick_xml::reader::Reader;
+use quick_xml::name::ResolveResult;
+use quick_xml::reader::NsReader;
 use reqwest::Client;
-use std::io::Read;
+use tokio::io::{AsyncRead, BufReader, ReadBuf};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio_util::io::StreamReader;
 use tracing::{info, warn, error};
 use url::Url;
 
-use crate::client::{build_client, get_with_retry};
-pub async fn parse_sitemap(sitemap_url: &str, proxy: Option<&str>, max_retries: u32) -> Vec<String> {
-    let client = build_client(proxy).expect("Failed to build HTTP client");
+use crate::client::{build_stream_client, get_with_retry, Pacing};
+use crate::retry::RetryPolicy;
+use crate::throttle::Throttle;
 
-    info!(url = %sitemap_url, "Downloading sitemap");
+/// How often a page is expected to change, per the sitemap protocol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ChangeFreq {
+    Always,
+    Hourly,
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+    Never,
+}
 
-    let xml = match fetch_xml(&client, sitemap_url, max_retries).await {
-        Some(body) => body,
-        None => {
-            error!(url = %sitemap_url, "Failed to fetch sitemap XML");
-            return Vec::new();
-        }
+impl ChangeFreq {
+    pub const ALL: [ChangeFreq; 7] = [
+        ChangeFreq::Always,
+        ChangeFreq::Hourly,
+        ChangeFreq::Daily,
+        ChangeFreq::Weekly,
+        ChangeFreq::Monthly,
+        ChangeFreq::Yearly,
+        ChangeFreq::Never,
+    ];
+
+    /// Inverse of `Display`; the protocol's values are lowercase, but case is
+    /// not enforced here. Validation reports values that are not lowercase.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|freq| freq.to_string().eq_ignore_ascii_case(name))
+    }
+}
+
+impl fmt::Display for ChangeFreq {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ChangeFreq::Always => "always",
+            ChangeFreq::Hourly => "hourly",
+            ChangeFreq::Daily => "daily",
+            ChangeFreq::Weekly => "weekly",
+            ChangeFreq::Monthly => "monthly",
+            ChangeFreq::Yearly => "yearly",
+            ChangeFreq::Never => "never",
+        };
+        f.pad(name)
+    }
+}
+
+/// One `<url>` (or, in an index, `<sitemap>`) entry and what the sitemap says about it.
+#[derive(Debug, Clone)]
+pub struct SitemapEntry {
+    pub loc: String,
+    /// `None` when missing or not a W3C datetime.
+    pub lastmod: Option<DateTime<FixedOffset>>,
+    /// `None` when missing or not one of the protocol's values.
+    pub changefreq: Option<ChangeFreq>,
+    /// As given, even outside 0.0-1.0; `None` when missing, not a number, NaN or infinite.
+    pub priority: Option<f64>,
+    /// The sitemap file that listed this entry: a child of an index, or the sitemap itself.
+    pub sitemap: String,
+}
+
+/// Parse a W3C datetime (https://www.w3.org/TR/NOTE-datetime): `2024`, `2024-05`,
+/// `2024-05-17`, or a date with `Thh:mm`, `Thh:mm:ss` or `Thh:mm:ss.s` and a
+/// `Z` or `+hh:mm` zone. Dates without a time are taken as midnight UTC.
+pub fn parse_w3c_datetime(value: &str) -> Option<DateTime<FixedOffset>> {
+    let value = value.trim();
+    if value.contains('T') {
+        return DateTime::parse_from_rfc3339(value)
+            .or_else(|_| DateTime::parse_from_str(value, "%Y-%m-%dT%H:%M%:z"))
+            .or_else(|_| DateTime::parse_from_str(&value.replacen('Z', "+00:00", 1), "%Y-%m-%dT%H:%M%:z"))
+            .ok();
+    }
+
+    // Exactly so many digits; `parse` alone would also take a sign.
+    let digits = |part: &str, len: usize| -> Option<u32> {
+        (part.len() == len && part.bytes().all(|b| b.is_ascii_digit())).then(|| part.parse().ok()).flatten()
+    };
+    let mut parts = value.splitn(3, '-');
+    let year = digits(parts.next()?, 4)? as i32;
+    let month = match parts.next() {
+        Some(m) => digits(m, 2)?,
+        None => 1,
+    };
+    let day = match parts.next() {
+        Some(d) => digits(d, 2)?,
+        None => 1,
     };
+    let date = NaiveDate::from_ymd_opt(year, month, day)?;
+    Some(date.and_hms_opt(0, 0, 0)?.and_utc().fixed_offset())
+}
+
+/// Child sitemaps of an index that are downloaded at the same time.
+const SITEMAP_FETCHES: usize = 8;
+
+/// Parsed entries waiting to be taken. Parsing pauses when it is full.
+const ENTRY_QUEUE: usize = 1024;
+
+/// The namespace every sitemap and sitemap index must declare.
+pub const SITEMAP_NS: &str = "http://www.sitemaps.org/schemas/sitemap/0.9";
+
+/// Most `<url>` entries a sitemap, or `<sitemap>` entries an index, may hold.
+const MAX_ENTRIES: usize = 50_000;
+
+/// Largest a sitemap may be once uncompressed.
+const MAX_BYTES: u64 = 50 * 1024 * 1024;
+
+/// Longest a `<loc>` may be, in characters.
+const MAX_LOC_LEN: usize = 2048;
+
+/// The first bytes of every gzip file.
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Whether a sitemap body is gzipped. Its first bytes decide when there are
+/// enough of them, since a `.gz` URL may be served already inflated and a
+/// gzipped sitemap may have any URL; otherwise the extension and the
+/// `Content-Type` are all there is to go by.
+fn is_gzipped(start: Option<&[u8]>, url: &str, content_type: Option<&str>) -> bool {
+    match start {
+        Some(start) if start.len() >= GZIP_MAGIC.len() => start.starts_with(&GZIP_MAGIC),
+        _ => {
+            let mime = content_type.and_then(|t| t.split(';').next()).unwrap_or_default().trim();
+            url.ends_with(".gz")
+                || mime.eq_ignore_ascii_case("application/gzip")
+                || mime.eq_ignore_ascii_case("application/x-gzip")
+        }
+    }
+}
+
+/// Whether a document lists pages or other sitemaps, going by its root element.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SitemapKind {
+    UrlSet,
+    Index,
+}
+
+/// A 1-based line and byte column in an uncompressed sitemap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+    pub line: u64,
+    pub column: u64,
+}
+
+/// A way in which a sitemap breaks the sitemaps.org protocol.
+#[derive(Debug, Clone)]
+pub struct Violation {
+    /// The sitemap file it is in.
+    pub sitemap: String,
+    /// `None` when the file could not be read at all.
+    pub at: Option<Position>,
+    pub message: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.at {
+            Some(at) => write!(f, "{}:{}:{}: {}", self.sitemap, at.line, at.column, self.message),
+            None => write!(f, "{}: {}", self.sitemap, self.message),
+        }
+    }
+}
 
-    let urls = extract_locs(&xml);
+/// A sitemap that could only be read in part, and why.
+#[derive(Debug, Clone)]
+pub struct PartialSitemap {
+    pub url: String,
+    pub reason: String,
+}
+
+/// The entries of one sitemap and its descendants, received as they are parsed.
+pub struct SitemapStream {
+    pub entries: mpsc::Receiver<SitemapEntry>,
+    traversal: JoinHandle<Vec<PartialSitemap>>,
+}
 
-    if urls.iter().all(|u| u.ends_with(".xml") || u.ends_with(".xml.gz")) {
-        let mut all_urls = Vec::new();
+impl SitemapStream {
+    /// The sitemaps that could only be read in part. Waits for parsing to
+    /// finish, so take every entry first.
+    pub async fn partial(self) -> Vec<PartialSitemap> {
+        self.traversal.await.expect("Sitemap parser panicked")
+    }
+}
+
+/// Where a traversal sends what it finds. Violations are only looked for
+/// when someone is listening for them.
+#[derive(Clone)]
+struct Outputs {
+    entries: Option<mpsc::Sender<SitemapEntry>>,
+    violations: Option<mpsc::Sender<Violation>>,
+}
+
+impl Outputs {
+    /// Whether nobody is listening any more.
+    fn closed(&self) -> bool {
+        self.entries.as_ref().is_none_or(|tx| tx.is_closed())
+            && self.violations.as_ref().is_none_or(|tx| tx.is_closed())
+    }
+
+    async fn violation(&self, violation: Violation) {
+        if let Some(tx) = &self.violations {
+            let _ = tx.send(violation).await;
+        }
+    }
+}
 
-        for child_sitemap in urls.iter() {
-            if let Some(child_xml) = fetch_xml(&client, child_sitemap, max_retries).await {
-                let child_urls = extract_locs(&child_xml);
-                info!(count = child_urls.len(), url = %child_sitemap, "Parsed child sitemap");
-                all_urls.extend(child_urls);
+/// What was left of one sitemap document once its page entries were sent on.
+struct Document {
+    kind: SitemapKind,
+    /// An index's child sitemaps.
+    children: Vec<String>,
+    /// Page entries read.
+    entries: usize,
+    /// Why some of the document could not be read, if it could not.
+    partial: Option<String>,
+}
+
+/// Every page URL in the sitemap at `sitemap_url`, received as it is parsed.
+/// Sitemap indexes are followed to any depth, streaming their children
+/// concurrently; a sitemap listed more than once, including by one of its own
+/// descendants, is only read once. A URL listed in several sitemaps comes
+/// through once per listing.
+pub fn parse_sitemap(
+    sitemap_url: &str,
+    proxy: Option<&str>,
+    retry: &RetryPolicy,
+    throttle: &Arc<Throttle>,
+) -> SitemapStream {
+    let (tx, rx) = mpsc::channel(ENTRY_QUEUE);
+    let outputs = Outputs { entries: Some(tx), violations: None };
+    let traversal = spawn_traversal(sitemap_url, proxy, retry, throttle, outputs);
+    SitemapStream { entries: rx, traversal }
+}
+
+/// Every sitemaps.org protocol violation in the sitemap at `sitemap_url` and,
+/// for an index, in all of its descendants, received as they are found.
+pub fn validate_sitemap(
+    sitemap_url: &str,
+    proxy: Option<&str>,
+    retry: &RetryPolicy,
+    throttle: &Arc<Throttle>,
+) -> mpsc::Receiver<Violation> {
+    let (tx, rx) = mpsc::channel(ENTRY_QUEUE);
+    let outputs = Outputs { entries: None, violations: Some(tx) };
+    spawn_traversal(sitemap_url, proxy, retry, throttle, outputs);
+    rx
+}
+
+fn spawn_traversal(
+    sitemap_url: &str,
+    proxy: Option<&str>,
+    retry: &RetryPolicy,
+    throttle: &Arc<Throttle>,
+    outputs: Outputs,
+) -> JoinHandle<Vec<PartialSitemap>> {
+    let client = build_stream_client(proxy).expect("Failed to build HTTP client");
+    let sitemap_url = sitemap_url.to_string();
+    let retry = retry.clone();
+    let throttle = Arc::clone(throttle);
+    tokio::spawn(async move { traverse(&client, &sitemap_url, &retry, &throttle, outputs).await })
+}
+
+/// Read the sitemap and everything it leads to, returning the sitemaps that
+/// could only be read in part.
+async fn traverse(
+    client: &Client,
+    sitemap_url: &str,
+    retry: &RetryPolicy,
+    throttle: &Arc<Throttle>,
+    outputs: Outputs,
+) -> Vec<PartialSitemap> {
+    info!(url = %sitemap_url, "Downloading sitemap");
+
+    let mut seen = HashSet::from([sitemap_url.to_string()]);
+    let mut pending = VecDeque::from([sitemap_url.to_string()]);
+    let mut reads = FuturesUnordered::new();
+    let mut total = 0usize;
+    let mut indexes = 0usize;
+    let mut partial = Vec::new();
+
+    loop {
+        while reads.len() < SITEMAP_FETCHES {
+            let Some(url) = pending.pop_front() else { break };
+            reads.push(read_sitemap(client, url, retry, throttle, &outputs));
+        }
+        let Some((url, document)) = reads.next().await else { break };
+        let Some(document) = document else {
+            error!(url = %url, "Failed to fetch sitemap XML");
+            let message = "could not be downloaded".to_string();
+            partial.push(PartialSitemap { url: url.clone(), reason: message.clone() });
+            outputs.violation(Violation { sitemap: url, at: None, message }).await;
+            continue;
+        };
+        if let Some(reason) = &document.partial {
+            partial.push(PartialSitemap { url: url.clone(), reason: reason.clone() });
+        }
+
+        match document.kind {
+            SitemapKind::Index => {
+                indexes += 1;
+                let listed = document.children.len();
+                let children: Vec<String> = document
+                    .children
+                    .into_iter()
+                    .filter(|loc| seen.insert(loc.clone()))
+                    .collect();
+                if children.len() < listed {
+                    warn!(url = %url, skipped = listed - children.len(), "Skipped child sitemaps already read");
+                }
+                info!(count = children.len(), url = %url, "Parsed sitemap index");
+                pending.extend(children);
             }
+            SitemapKind::UrlSet => {
+                if url != sitemap_url {
+                    info!(count = document.entries, url = %url, "Parsed child sitemap");
+                }
+                total += document.entries;
+            }
+        }
+        if outputs.closed() {
+            return partial;
         }
+    }
 
-        all_urls.sort();
-        all_urls.dedup();
-        info!(count = all_urls.len(), "Total unique URLs from sitemap index");
-        all_urls
+    if indexes > 0 {
+        info!(count = total, sitemaps = seen.len(), "Total URLs from sitemap index");
     } else {
-        info!(count = urls.len(), "Parsed sitemap URLs");
-        let mut sorted = urls;
-        sorted.sort();
-        sorted.dedup();
-        sorted
+        info!(count = total, "Parsed sitemap URLs");
     }
+    partial
 }
 
-async fn fetch_xml(client: &Client, url: &str, max_retries: u32) -> Option<String> {
-    let is_gz = url.ends_with(".gz");
-
-    match get_with_retry(client, url, max_retries).await {
+/// Stream one sitemap document, sending its page entries and any violations
+/// on as they are parsed; `None` when it could not be fetched. Gzipped
+/// sitemaps, told apart by their first bytes, are inflated on the fly, so no
+/// more than a read buffer of the file is held at a time.
+async fn read_sitemap(
+    client: &Client,
+    url: String,
+    retry: &RetryPolicy,
+    throttle: &Arc<Throttle>,
+    outputs: &Outputs,
+) -> (String, Option<Document>) {
+    let resp = match get_with_retry(client, &url, retry, Pacing::Throttle(throttle)).await.result {
+        Ok(resp) if resp.status().is_success() => resp,
         Ok(resp) => {
-            if !resp.status().is_success() {
-                warn!(status = %resp.status(), url = %url, "HTTP error fetching sitemap");
-                return None;
+            warn!(status = %resp.status(), url = %url, "HTTP error fetching sitemap");
+            return (url, None);
+        }
+        Err(e) => {
+            error!(url = %url, error = %e, "Request failed");
+            return (url, None);
+        }
+    };
+
+    let content_type = resp
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string);
+
+    // Each chunk waits for the bandwidth cap before the parser gets it.
+    let throttle = Arc::clone(throttle);
+    let chunks = resp.bytes_stream().then(move |chunk| {
+        let throttle = Arc::clone(&throttle);
+        async move {
+            if let Ok(bytes) = &chunk {
+                throttle.download(bytes.len()).await;
             }
+            chunk
+        }
+    });
+    let mut chunks = Box::pin(chunks.peekable());
+    let start = match chunks.as_mut().peek().await {
+        Some(Ok(first)) => Some(first.slice(..first.len().min(GZIP_MAGIC.len()))),
+        // A failed read is left for the parser to report.
+        _ => None,
+    };
+    let gzipped = is_gzipped(start.as_deref(), &url, content_type.as_deref());
+    let body = StreamReader::new(chunks.map_err(io::Error::other));
+    let body: Box<dyn AsyncRead + Unpin + Send> = if gzipped {
+        let mut decoder = GzipDecoder::new(body);
+        decoder.multiple_members(true);
+        Box::new(decoder)
+    } else {
+        Box::new(body)
+    };
 
-            if is_gz {
-                match resp.bytes().await {
-                    Ok(bytes) => {
-                        info!(url = %url, compressed_bytes = bytes.len(), "Downloaded .gz sitemap");
-                        match decompress_gz(&bytes) {
-                            Ok(text) => Some(text),
-                            Err(e) => {
-                                error!(url = %url, error = %e, "Failed to decompress .gz");
-                                None
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        error!(url = %url, error = %e, "Failed to read response bytes");
-                        None
+    let mut reader = NsReader::from_reader(BufReader::new(Lines::new(body)));
+    let mut parser = EntryParser::new(&url, outputs.violations.is_some());
+    let mut buf = Vec::new();
+    let mut children = Vec::new();
+    let mut entries = 0usize;
+    let mut errors = 0usize;
+    let mut first_error: Option<String> = None;
+    let mut stopped = false;
+    let mut interrupted: Option<String> = None;
+    let mut cancelled = false;
+
+    loop {
+        let offset = reader.buffer_position();
+        let at = reader.get_mut().get_mut().locate(offset);
+        let (done, finished) = match reader.read_resolved_event_into_async(&mut buf).await {
+            Ok((ns, event)) => (parser.feed(&ns, &event, at), matches!(event, Event::Eof)),
+            // The body could not be read, which says nothing about its markup.
+            Err(quick_xml::Error::Io(e)) => {
+                let offset = reader.buffer_position();
+                let at = reader.get_mut().get_mut().locate(offset);
+                let (what, detail) = read_failure(&e);
+                warn!(url = %url, line = at.line, column = at.column, error = %detail, "Sitemap {}", what);
+                parser.violation(at, format!("{}: {}", what, detail));
+                interrupted = Some(format!("{} at {}:{}: {}", what, at.line, at.column, detail));
+                stopped = true;
+                // The entry being read may have lost fields, so it is dropped.
+                parser.entry = None;
+                (None, true)
+            }
+            Err(e) => {
+                let error_at = reader.error_position();
+                let at = reader.get_mut().get_mut().locate(error_at);
+                warn!(url = %url, line = at.line, column = at.column, error = %e, "XML parse error");
+                parser.violation(at, format!("XML error: {}", e));
+                errors += 1;
+                first_error.get_or_insert_with(|| format!("{}:{}: {}", at.line, at.column, e));
+                // Only ill-formed markup can be skipped; anything else ends the document.
+                if let quick_xml::Error::IllFormed(ill_formed) = &e {
+                    if let IllFormedError::MismatchedEndTag { .. } = ill_formed {
+                        // The reader takes it as the end of the open element.
+                        parser.depth = parser.depth.saturating_sub(1);
                     }
+                    (None, false)
+                } else {
+                    stopped = true;
+                    (parser.close_entry(), true)
                 }
+            }
+        };
+
+        if let Some(entry) = done {
+            if parser.kind() == SitemapKind::Index {
+                children.push(entry.loc);
             } else {
-                match resp.text().await {
-                    Ok(body) => Some(body),
-                    Err(e) => {
-                        error!(url = %url, error = %e, "Failed to read response body");
-                        None
+                entries += 1;
+                if let Some(tx) = &outputs.entries {
+                    if tx.send(entry).await.is_err() {
+                        cancelled = true;
+                        break;
                     }
                 }
             }
         }
-        Err(e) => {
-            error!(url = %url, error = %e, "Request failed");
-            None
+        if finished {
+            break;
+        }
+        for violation in parser.violations.drain(..) {
+            outputs.violation(violation).await;
+        }
+        buf.clear();
+    }
+
+    let lines = reader.get_mut().get_mut();
+    let end = lines.locate(lines.read);
+    if lines.read > MAX_BYTES && !stopped {
+        let message = format!("is {} bytes uncompressed, more than the {} allowed", lines.read, MAX_BYTES);
+        parser.violation(end, message);
+    }
+    if parser.depth > 0 && !stopped && !cancelled {
+        let message = "file ends before its closing tags";
+        warn!(url = %url, line = end.line, column = end.column, "Sitemap ends before its closing tags");
+        parser.violation(end, message);
+        errors += 1;
+        first_error.get_or_insert_with(|| format!("{}:{}: {}", end.line, end.column, message));
+        stopped = true;
+    }
+    for violation in parser.violations.drain(..) {
+        outputs.violation(violation).await;
+    }
+
+    let partial = match first_error {
+        _ if interrupted.is_some() => interrupted,
+        Some(error) if stopped => Some(format!("stopped by an XML error at {}", error)),
+        Some(error) if errors == 1 => Some(format!("XML error at {}", error)),
+        Some(error) => Some(format!("{} XML errors, the first at {}", errors, error)),
+        None => None,
+    };
+    let document = Document { kind: parser.kind(), children, entries, partial };
+    (url, Some(document))
+}
+
+/// Why the body stopped being readable, as what went wrong and the error: a
+/// transfer that broke off, or compressed data that does not inflate.
+fn read_failure(e: &io::Error) -> (&'static str, String) {
+    use std::error::Error;
+
+    if e.kind() == io::ErrorKind::InvalidData {
+        return ("gzip data is corrupt", e.to_string());
+    }
+    let detail = match e.source() {
+        Some(source) => format!("{}: {}", e, source),
+        None => e.to_string(),
+    };
+    ("download interrupted", detail)
+}
+
+/// Counts the bytes read through it and where each line starts, so offsets
+/// from the XML reader can be turned into line and column numbers.
+struct Lines<R> {
+    inner: R,
+    /// Bytes read so far.
+    read: u64,
+    /// Offsets of the newlines read but not yet passed by `locate`.
+    newlines: VecDeque<u64>,
+    line: u64,
+    line_start: u64,
+}
+
+impl<R> Lines<R> {
+    fn new(inner: R) -> Self {
+        Self {
+            inner,
+            read: 0,
+            newlines: VecDeque::new(),
+            line: 1,
+            line_start: 0,
+        }
+    }
+
+    /// The position of byte `offset`. Offsets must not go backwards between calls.
+    fn locate(&mut self, offset: u64) -> Position {
+        while let Some(&newline) = self.newlines.front().filter(|&&n| n < offset) {
+            self.newlines.pop_front();
+            self.line += 1;
+            self.line_start = newline + 1;
+        }
+        Position {
+            line: self.line,
+            column: offset.saturating_sub(self.line_start) + 1,
         }
     }
 }
 
-fn decompress_gz(data: &[u8]) -> Result<String, std::io::Error> {
-    let mut decoder = GzDecoder::new(data);
-    let mut xml = String::new();
-    decoder.read_to_string(&mut xml)?;
-    Ok(xml)
+impl<R: AsyncRead + Unpin> AsyncRead for Lines<R> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        let polled = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = polled {
+            let start = self.read;
+            let new = &buf.filled()[before..];
+            let newlines = new.iter().enumerate().filter(|(_, &b)| b == b'\n').map(|(i, _)| start + i as u64);
+            self.newlines.extend(newlines.collect::<Vec<_>>());
+            self.read += new.len() as u64;
+        }
+        polled
+    }
 }
 
-fn extract_locs(xml: &str) -> Vec<String> {
-    let mut reader = Reader::from_str(xml);
-    let mut urls = Vec::new();
-    let mut inside_loc = false;
+/// The child element of an entry whose text is being read.
+#[derive(Clone, Copy)]
+enum Field {
+    Loc,
+    Lastmod,
+    Changefreq,
+    Priority,
+}
 
-    loop {
-        match reader.read_event() {
-            Ok(Event::Start(ref e)) if e.name().as_ref() == b"loc" => {
-                inside_loc = true;
-            }
-            Ok(Event::Text(ref e)) if inside_loc => {
-                if let Ok(text) = e.unescape() {
-                    let trimmed = text.trim().to_string();
-                    if !trimmed.is_empty() {
-                        if Url::parse(&trimmed).is_ok() {
-                            urls.push(trimmed);
+impl Field {
+    fn from_local_name(name: &[u8]) -> Option<Self> {
+        match name {
+            b"loc" => Some(Field::Loc),
+            b"lastmod" => Some(Field::Lastmod),
+            b"changefreq" => Some(Field::Changefreq),
+            b"priority" => Some(Field::Priority),
+            _ => None,
+        }
+    }
+}
+
+/// Builds `<url>` or `<sitemap>` entries from one document's events, one event
+/// at a time, checking them against the protocol when asked to. Elements are
+/// matched by local name within the root element's namespace, so prefixed
+/// sitemaps work and extensions such as `<image:loc>` are left alone.
+struct EntryParser {
+    sitemap_url: String,
+    /// The root element's namespace; `None` when it has none.
+    ns: Option<Vec<u8>>,
+    /// The sitemap's host, which every `<loc>` must be on.
+    host: Option<String>,
+    validate: bool,
+    kind: Option<SitemapKind>,
+    entry: Option<SitemapEntry>,
+    /// Where the current entry started.
+    entry_at: Position,
+    field: Option<Field>,
+    /// Where the current field started.
+    field_at: Position,
+    /// Elements open at the current event.
+    depth: usize,
+    text: String,
+    /// Entries completed so far, valid or not.
+    count: usize,
+    /// Found since last drained; only filled when validating.
+    violations: Vec<Violation>,
+}
+
+impl EntryParser {
+    fn new(sitemap_url: &str, validate: bool) -> Self {
+        let start = Position { line: 1, column: 1 };
+        Self {
+            sitemap_url: sitemap_url.to_string(),
+            ns: None,
+            host: Url::parse(sitemap_url).ok().and_then(|u| u.host_str().map(str::to_string)),
+            validate,
+            kind: None,
+            entry: None,
+            entry_at: start,
+            field: None,
+            field_at: start,
+            depth: 0,
+            text: String::new(),
+            count: 0,
+            violations: Vec::new(),
+        }
+    }
+
+    /// Whether the root element made the document an index.
+    fn kind(&self) -> SitemapKind {
+        self.kind.unwrap_or(SitemapKind::UrlSet)
+    }
+
+    fn violation(&mut self, at: Position, message: impl Into<String>) {
+        if self.validate {
+            self.violations.push(Violation {
+                sitemap: self.sitemap_url.clone(),
+                at: Some(at),
+                message: message.into(),
+            });
+        }
+    }
+
+    /// Whether an element resolved to `ns` is part of the sitemap rather than an extension.
+    fn in_sitemap_ns(&self, ns: &ResolveResult) -> bool {
+        match ns {
+            ResolveResult::Bound(ns) => self.ns.as_deref() == Some(ns.as_ref()),
+            ResolveResult::Unbound => self.ns.is_none(),
+            ResolveResult::Unknown(_) => false,
+        }
+    }
+
+    /// Take the event that starts at `at`, whose element resolved to `ns`,
+    /// returning the entry it completed if that entry has a valid absolute `<loc>`.
+    fn feed(&mut self, ns: &ResolveResult, event: &Event, at: Position) -> Option<SitemapEntry> {
+        match event {
+            Event::Start(_) => self.depth += 1,
+            Event::End(_) => self.depth = self.depth.saturating_sub(1),
+            _ => {}
+        }
+        match event {
+            Event::Start(e) | Event::Empty(e) if self.kind.is_none() => {
+                self.kind = Some(match e.local_name().as_ref() {
+                    b"sitemapindex" => SitemapKind::Index,
+                    b"urlset" => SitemapKind::UrlSet,
+                    other => {
+                        let root = String::from_utf8_lossy(other).into_owned();
+                        warn!(url = %self.sitemap_url, root = %root, "Unexpected root element, reading as <urlset>");
+                        self.violation(at, format!("root element is <{}>, expected <urlset> or <sitemapindex>", root));
+                        SitemapKind::UrlSet
+                    }
+                });
+                match ns {
+                    ResolveResult::Bound(bound) => {
+                        self.ns = Some(bound.as_ref().to_vec());
+                        if bound.as_ref() != SITEMAP_NS.as_bytes() {
+                            let found = String::from_utf8_lossy(bound.as_ref()).into_owned();
+                            self.violation(at, format!("namespace is `{}`, expected `{}`", found, SITEMAP_NS));
                         }
                     }
+                    ResolveResult::Unbound => {
+                        self.violation(at, format!("missing namespace, expected xmlns=\"{}\"", SITEMAP_NS));
+                    }
+                    ResolveResult::Unknown(prefix) => {
+                        let prefix = String::from_utf8_lossy(prefix).into_owned();
+                        self.violation(at, format!("namespace prefix `{}` is not declared", prefix));
+                    }
                 }
             }
-            Ok(Event::End(ref e)) if e.name().as_ref() == b"loc" => {
-                inside_loc = false;
-            }
-            Ok(Event::Eof) => break,
-            Err(e) => {
-                warn!(error = %e, "XML parse error");
-                break;
+            Event::Start(e) if self.in_sitemap_ns(ns) => match e.local_name().as_ref() {
+                b"url" | b"sitemap" => {
+                    // An entry left open by broken markup ends where the next one starts.
+                    let unfinished = self.close_entry();
+                    self.entry = Some(SitemapEntry {
+                        loc: String::new(),
+                        lastmod: None,
+                        changefreq: None,
+                        priority: None,
+                        sitemap: self.sitemap_url.clone(),
+                    });
+                    self.entry_at = at;
+                    return unfinished;
+                }
+                name => {
+                    self.field = Field::from_local_name(name);
+                    self.field_at = at;
+                    self.text.clear();
+                }
+            },
+            Event::Text(e) if self.field.is_some() => match e.unescape() {
+                Ok(unescaped) => self.text.push_str(&unescaped),
+                Err(err) => {
+                    warn!(url = %self.sitemap_url, error = %err, "Unreadable text in sitemap, keeping it as written");
+                    self.violation(at, format!("XML error: {}", err));
+                    self.text.push_str(&String::from_utf8_lossy(e));
+                }
+            },
+            Event::CData(e) if self.field.is_some() => {
+                self.text.push_str(&String::from_utf8_lossy(e));
             }
+            Event::End(e) if self.in_sitemap_ns(ns) => match (e.local_name().as_ref(), self.field.take(), self.entry.is_some()) {
+                (b"url" | b"sitemap", _, true) => return self.close_entry(),
+                (_, Some(field), true) => self.set_field(field),
+                _ => {}
+            },
+            Event::Eof => return self.close_entry(),
             _ => {}
         }
+        None
     }
 
-    urls
+    /// End the open entry, returning it if it has a valid absolute `<loc>`.
+    fn close_entry(&mut self) -> Option<SitemapEntry> {
+        let done = self.entry.take()?;
+        self.count += 1;
+        if self.count == MAX_ENTRIES + 1 {
+            let what = if self.kind() == SitemapKind::Index { "sitemaps" } else { "URLs" };
+            self.violation(self.entry_at, format!("more than {} {}", MAX_ENTRIES, what));
+        }
+        if done.loc.is_empty() {
+            let name = if self.kind() == SitemapKind::Index { "sitemap" } else { "url" };
+            self.violation(self.entry_at, format!("<{}> has no <loc>", name));
+            return None;
+        }
+        Url::parse(&done.loc).is_ok().then_some(done)
+    }
+
+    /// Store the text read for `field` in the open entry.
+    fn set_field(&mut self, field: Field) {
+        let value = self.text.trim().to_string();
+        let at = self.field_at;
+        match field {
+            Field::Loc => {
+                self.check_loc(&value);
+                self.entry_mut().loc = value;
+            }
+            Field::Lastmod => {
+                let lastmod = parse_w3c_datetime(&value);
+                if lastmod.is_none() {
+                    self.violation(at, format!("lastmod `{}` is not a W3C datetime", value));
+                }
+                self.entry_mut().lastmod = lastmod;
+            }
+            Field::Changefreq => {
+                let changefreq = ChangeFreq::from_name(&value);
+                self.entry_mut().changefreq = changefreq;
+                match changefreq {
+                    Some(freq) if freq.to_string() == value => {}
+                    Some(freq) => self.violation(at, format!("changefreq `{}` must be lowercase `{}`", value, freq)),
+                    None => {
+                        let known: Vec<String> = ChangeFreq::ALL.iter().map(|f| f.to_string()).collect();
+                        self.violation(at, format!("changefreq `{}` is not one of {}", value, known.join(", ")));
+                    }
+                }
+            }
+            Field::Priority => {
+                let priority: Option<f64> = value.parse().ok();
+                self.entry_mut().priority = priority.filter(|p| p.is_finite());
+                match priority {
+                    Some(p) if (0.0..=1.0).contains(&p) => {}
+                    Some(p) if !p.is_finite() => self.violation(at, format!("priority `{}` is not a finite number", value)),
+                    Some(_) => self.violation(at, format!("priority `{}` is not between 0.0 and 1.0", value)),
+                    None => self.violation(at, format!("priority `{}` is not a number", value)),
+                }
+            }
+        }
+    }
+
+    fn entry_mut(&mut self) -> &mut SitemapEntry {
+        self.entry.as_mut().expect("inside an entry")
+    }
+
+    /// A `<loc>` must be a short absolute URL on the sitemap's own host.
+    fn check_loc(&mut self, loc: &str) {
+        if !self.validate {
+            return;
+        }
+        let at = self.field_at;
+        if loc.chars().count() > MAX_LOC_LEN {
+            self.violation(at, format!("loc is longer than {} characters", MAX_LOC_LEN));
+        }
+        match Url::parse(loc) {
+            Ok(url) if url.host_str() != self.host.as_deref() => {
+                let host = url.host_str().unwrap_or_default();
+                let expected = self.host.as_deref().unwrap_or_default();
+                self.violation(at, format!("loc `{}` is on host `{}`, not the sitemap's host `{}`", loc, host, expected));
+            }
+            Ok(_) => {}
+            Err(_) => self.violation(at, format!("loc `{}` is not an absolute URL", loc)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_compression::tokio::bufread::GzipEncoder;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Serve `body` with `content_type` to every request, returning the server's address.
+    async fn serve(body: Vec<u8>, content_type: &'static str) -> std::net::SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let mut request = [0u8; 4096];
+                let _ = socket.read(&mut request).await;
+                let head = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    content_type,
+                    body.len()
+                );
+                let _ = socket.write_all(head.as_bytes()).await;
+                let _ = socket.write_all(&body).await;
+            }
+        });
+        addr
+    }
+
+    async fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut gzipped = Vec::new();
+        GzipEncoder::new(data).read_to_end(&mut gzipped).await.unwrap();
+        gzipped
+    }
+
+    async fn locs(url: &str) -> (Vec<String>, usize) {
+        let retry = RetryPolicy {
+            max_retries: 0,
+            base: std::time::Duration::ZERO,
+            max_wait: std::time::Duration::ZERO,
+            jitter: crate::retry::Jitter::None,
+            statuses: Vec::new(),
+        };
+        let mut stream = parse_sitemap(url, None, &retry, &Arc::default());
+        let mut locs = Vec::new();
+        while let Some(entry) = stream.entries.recv().await {
+            locs.push(entry.loc);
+        }
+        (locs, stream.partial().await.len())
+    }
+
+    const URLSET: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+  <url><loc>https://example.com/a</loc></url>
+  <url><loc>https://example.com/b</loc></url>
+</urlset>
+"#;
+
+    #[test]
+    fn gzip_is_told_by_its_first_bytes_before_the_url_or_type() {
+        assert!(is_gzipped(Some(&[0x1f, 0x8b]), "https://example.com/sitemap", Some("text/xml")));
+        assert!(!is_gzipped(Some(b"<?"), "https://example.com/sitemap.xml.gz", Some("application/gzip")));
+        assert!(is_gzipped(None, "https://example.com/sitemap", Some("application/x-gzip")));
+        assert!(is_gzipped(Some(&[0x1f]), "https://example.com/sitemap", Some("Application/Gzip; q=1")));
+        assert!(is_gzipped(None, "https://example.com/sitemap.xml.gz", None));
+        assert!(!is_gzipped(None, "https://example.com/sitemap", Some("application/xml")));
+    }
+
+    #[tokio::test]
+    async fn gzipped_sitemaps_are_read_whatever_their_url() {
+        let addr = serve(gzip(URLSET.as_bytes()).await, "application/octet-stream").await;
+        let (locs, partial) = locs(&format!("http://{}/sitemap", addr)).await;
+        assert_eq!(locs, ["https://example.com/a", "https://example.com/b"]);
+        assert_eq!(partial, 0);
+    }
+
+    #[tokio::test]
+    async fn inflated_sitemaps_are_read_despite_a_gz_url() {
+        let addr = serve(URLSET.as_bytes().to_vec(), "application/xml").await;
+        let (locs, partial) = locs(&format!("http://{}/sitemap.xml.gz", addr)).await;
+        assert_eq!(locs.len(), 2);
+        assert_eq!(partial, 0);
+    }
+
+    fn datetime(value: &str) -> Option<String> {
+        parse_w3c_datetime(value).map(|d| d.to_rfc3339())
+    }
+
+    #[test]
+    fn w3c_dates_without_a_time_are_midnight_utc() {
+        assert_eq!(datetime("2024").as_deref(), Some("2024-01-01T00:00:00+00:00"));
+        assert_eq!(datetime("2024-05").as_deref(), Some("2024-05-01T00:00:00+00:00"));
+        assert_eq!(datetime("2024-05-17").as_deref(), Some("2024-05-17T00:00:00+00:00"));
+        assert_eq!(datetime(" 2024-05-17\n").as_deref(), Some("2024-05-17T00:00:00+00:00"));
+    }
+
+    #[test]
+    fn w3c_times_need_a_zone_and_may_have_fractions() {
+        assert_eq!(datetime("2024-05-17T10:30Z").as_deref(), Some("2024-05-17T10:30:00+00:00"));
+        assert_eq!(datetime("2024-05-17T10:30+02:00").as_deref(), Some("2024-05-17T10:30:00+02:00"));
+        assert_eq!(datetime("2024-05-17T10:30:15-05:00").as_deref(), Some("2024-05-17T10:30:15-05:00"));
+        assert_eq!(datetime("2024-05-17T10:30:15.25Z").as_deref(), Some("2024-05-17T10:30:15.250+00:00"));
+    }
+
+    #[test]
+    fn invalid_w3c_datetimes_are_rejected() {
+        for value in [
+            "",
+            "24",
+            "+202",
+            "2024-5",
+            "2024-05-7",
+            "2024-+5",
+            "2024-13",
+            "2024-02-30",
+            "2024/05/17",
+            "17-05-2024",
+            "2024-05-17T10:30",
+            "2024-05-17T25:00Z",
+            "2024-05-17 10:30Z",
+            "yesterday",
+        ] {
+            assert_eq!(datetime(value), None, "{}", value);
+        }
+    }
+
+    /// The violations found for `value` as the given field of a `<url>` entry.
+    fn field_violations(field: Field, value: &str) -> (SitemapEntry, Vec<String>) {
+        let mut parser = EntryParser::new("https://example.com/sitemap.xml", true);
+        parser.entry = Some(SitemapEntry {
+            loc: String::new(),
+            lastmod: None,
+            changefreq: None,
+            priority: None,
+            sitemap: parser.sitemap_url.clone(),
+        });
+        parser.text = value.to_string();
+        parser.set_field(field);
+        let entry = parser.entry.take().expect("entry still open");
+        (entry, parser.violations.into_iter().map(|v| v.message).collect())
+    }
+
+    #[test]
+    fn changefreq_must_be_lowercase() {
+        let (entry, violations) = field_violations(Field::Changefreq, "daily");
+        assert_eq!(entry.changefreq, Some(ChangeFreq::Daily));
+        assert!(violations.is_empty());
+
+        let (entry, violations) = field_violations(Field::Changefreq, "Daily");
+        assert_eq!(entry.changefreq, Some(ChangeFreq::Daily));
+        assert_eq!(violations, ["changefreq `Daily` must be lowercase `daily`"]);
+
+        let (entry, violations) = field_violations(Field::Changefreq, "often");
+        assert_eq!(entry.changefreq, None);
+        assert_eq!(violations.len(), 1);
+    }
+
+    #[test]
+    fn non_finite_priorities_are_reported() {
+        for value in ["NaN", "inf", "-infinity"] {
+            let (entry, violations) = field_violations(Field::Priority, value);
+            assert_eq!(entry.priority, None, "{}", value);
+            assert_eq!(violations, [format!("priority `{}` is not a finite number", value)]);
+        }
+
+        let (entry, violations) = field_violations(Field::Priority, "1.5");
+        assert_eq!(entry.priority, Some(1.5));
+        assert_eq!(violations, ["priority `1.5` is not between 0.0 and 1.0"]);
+    }
 }
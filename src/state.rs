@@ -0,0 +1,406 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tracing::{info, warn};
+
+use crate::checker::{Outcome, UrlStatus};
+use crate::crawler::Visited;
+use crate::extractor::{Directive, ResourceKind, LINK_ELEMENTS};
+use crate::link_graph::{LinkEdge, LinkGraph};
+
+/// Append-only journal of crawl progress: pages seen, pages fetched, filtered links and link edges.
+const CRAWL_LOG: &str = "crawl.log";
+/// Append-only journal of checked URLs and their status.
+const CHECK_LOG: &str = "checked.log";
+
+/// What one crawl had done when the previous run stopped.
+#[derive(Debug, Default)]
+pub struct SavedCrawl {
+    /// Normalized URL -> latest record. Pages not in `fetched` are the frontier.
+    pub visited: HashMap<String, Visited>,
+    pub fetched: HashSet<String>,
+    pub excluded: HashSet<String>,
+    pub nofollowed: HashSet<String>,
+}
+
+/// Crawl frontier, visited set and completed checks kept in a state directory,
+/// so an interrupted run can be resumed without fetching anything twice.
+///
+/// Both journals are plain tab-separated text, one record per line, flushed as
+/// they are written. A record for a URL that is already known replaces it. A
+/// record only counts once its line break is written, so a last line cut short
+/// by a crash is dropped from the file when loading.
+pub struct StateStore {
+    crawls: Mutex<HashMap<String, SavedCrawl>>,
+    links: Mutex<Vec<(String, LinkEdge)>>,
+    checked: HashMap<String, Outcome>,
+    crawl_log: Mutex<BufWriter<File>>,
+    check_log: Mutex<BufWriter<File>>,
+}
+
+impl StateStore {
+    /// Load whatever state `dir` holds (creating it if needed) and keep appending to it.
+    pub fn open(dir: &Path) -> std::io::Result<Self> {
+        std::fs::create_dir_all(dir)?;
+
+        let mut crawls: HashMap<String, SavedCrawl> = HashMap::new();
+        let mut links = Vec::new();
+        let mut checked = HashMap::new();
+        let mut skipped = 0;
+
+        let (crawl_lines, torn) = read_lines(&dir.join(CRAWL_LOG))?;
+        skipped += usize::from(torn);
+        for line in crawl_lines {
+            let fields: Vec<&str> = line.split('\t').collect();
+            let parsed = match fields.as_slice() {
+                ["page", start, url, depth, reachable, kind, directives, aliases] => {
+                    parse_page(depth, reachable, kind, directives, aliases).map(|page| {
+                        crawl_entry(&mut crawls, start).visited.insert(url.to_string(), page);
+                    })
+                }
+                ["fetched", start, url] => {
+                    crawl_entry(&mut crawls, start).fetched.insert(url.to_string());
+                    Some(())
+                }
+                ["excluded", start, url] => {
+                    crawl_entry(&mut crawls, start).excluded.insert(url.to_string());
+                    Some(())
+                }
+                ["nofollow", start, url] => {
+                    crawl_entry(&mut crawls, start).nofollowed.insert(url.to_string());
+                    Some(())
+                }
+                ["link", target, source, element, anchor_text] => {
+                    LINK_ELEMENTS.iter().find(|e| *e == element).map(|&element| {
+                        links.push((
+                            target.to_string(),
+                            LinkEdge {
+                                source: source.to_string(),
+                                anchor_text: anchor_text.to_string(),
+                                element,
+                            },
+                        ));
+                    })
+                }
+                _ => None,
+            };
+            if parsed.is_none() {
+                skipped += 1;
+            }
+        }
+
+        let (check_lines, torn) = read_lines(&dir.join(CHECK_LOG))?;
+        skipped += usize::from(torn);
+        for line in check_lines {
+            let fields: Vec<&str> = line.split('\t').collect();
+            // Records written before the content type was kept have one field less.
+            let (url, code, redirect, retries, millis, content_type, text) = match fields.as_slice() {
+                [url, code, redirect, retries, millis, content_type, text] => {
+                    (url, code, redirect, retries, millis, *content_type, text)
+                }
+                [url, code, redirect, retries, millis, text] => (url, code, redirect, retries, millis, "", text),
+                _ => {
+                    skipped += 1;
+                    continue;
+                }
+            };
+            let Ok(retries) = retries.parse() else {
+                skipped += 1;
+                continue;
+            };
+            let response_time = match *millis {
+                "" => None,
+                millis => match millis.parse() {
+                    Ok(millis) => Some(Duration::from_millis(millis)),
+                    Err(_) => {
+                        skipped += 1;
+                        continue;
+                    }
+                },
+            };
+            let status_code = match *code {
+                "" => None,
+                code => match code.parse() {
+                    Ok(code) => Some(code),
+                    Err(_) => {
+                        skipped += 1;
+                        continue;
+                    }
+                },
+            };
+            checked.insert(
+                url.to_string(),
+                Outcome {
+                    status_code,
+                    status_text: text.to_string(),
+                    redirect_url: (!redirect.is_empty()).then(|| redirect.to_string()),
+                    content_type: (!content_type.is_empty()).then(|| content_type.to_string()),
+                    retries,
+                    response_time,
+                },
+            );
+        }
+
+        if skipped > 0 {
+            warn!(dir = %dir.display(), lines = skipped, "Skipped unreadable state records");
+        }
+        if !crawls.is_empty() || !checked.is_empty() {
+            info!(
+                dir = %dir.display(),
+                crawls = crawls.len(),
+                pages = crawls.values().map(|c| c.visited.len()).sum::<usize>(),
+                fetched = crawls.values().map(|c| c.fetched.len()).sum::<usize>(),
+                checked = checked.len(),
+                "Resuming from saved state"
+            );
+        }
+
+        Ok(Self {
+            crawls: Mutex::new(crawls),
+            links: Mutex::new(links),
+            checked,
+            crawl_log: Mutex::new(append(&dir.join(CRAWL_LOG))?),
+            check_log: Mutex::new(append(&dir.join(CHECK_LOG))?),
+        })
+    }
+
+    /// Hand over the saved progress of the crawl started at `start_url`; empty if there is none.
+    pub fn take_crawl(&self, start_url: &str) -> SavedCrawl {
+        self.crawls
+            .lock()
+            .expect("state poisoned")
+            .remove(start_url)
+            .unwrap_or_default()
+    }
+
+    /// Replay the saved link edges into `graph`.
+    pub fn restore_links(&self, graph: &mut LinkGraph) {
+        for (target, edge) in self.links.lock().expect("state poisoned").drain(..) {
+            graph.add(&target, edge);
+        }
+    }
+
+    /// The result of a check finished by a previous run.
+    pub fn checked(&self, url: &str) -> Option<&Outcome> {
+        self.checked.get(url)
+    }
+
+    pub fn record_page(&self, start_url: &str, url: &str, page: &Visited) {
+        let [depth, reachable, kind, directives, aliases] = page_fields(page);
+        self.write_crawl(&["page", start_url, url, &depth, &reachable, &kind, &directives, &aliases]);
+    }
+
+    /// `url` has been fetched and every link on it recorded.
+    pub fn record_fetched(&self, start_url: &str, url: &str) {
+        self.write_crawl(&["fetched", start_url, url]);
+    }
+
+    pub fn record_excluded(&self, start_url: &str, url: &str) {
+        self.write_crawl(&["excluded", start_url, url]);
+    }
+
+    pub fn record_nofollowed(&self, start_url: &str, url: &str) {
+        self.write_crawl(&["nofollow", start_url, url]);
+    }
+
+    pub fn record_link(&self, target: &str, edge: &LinkEdge) {
+        self.write_crawl(&["link", target, &edge.source, edge.element, &edge.anchor_text]);
+    }
+
+    pub fn record_status(&self, status: &UrlStatus) {
+        let code = status.status_code.map(|c| c.to_string()).unwrap_or_default();
+        let redirect = status.redirect_url.as_deref().unwrap_or_default();
+        let retries = status.retries.to_string();
+        let millis = status.response_time.map(|t| t.as_millis().to_string()).unwrap_or_default();
+        let content_type = status.content_type.as_deref().unwrap_or_default();
+        write_record(
+            &self.check_log,
+            &[&status.url, &code, redirect, &retries, &millis, content_type, &status.status_text],
+        );
+    }
+
+    fn write_crawl(&self, fields: &[&str]) {
+        write_record(&self.crawl_log, fields);
+    }
+}
+
+fn crawl_entry<'a>(crawls: &'a mut HashMap<String, SavedCrawl>, start_url: &str) -> &'a mut SavedCrawl {
+    crawls.entry(start_url.to_string()).or_default()
+}
+
+/// A page's depth, reachability, kind, directives and aliases as saved; the inverse of `parse_page`.
+pub fn page_fields(page: &Visited) -> [String; 5] {
+    let directives: Vec<String> = page.directives.iter().map(|d| d.to_string()).collect();
+    [
+        page.depth.to_string(),
+        if page.reachable { "1" } else { "0" }.to_string(),
+        page.kind.to_string(),
+        directives.join(","),
+        page.aliases.join(" "),
+    ]
+}
+
+pub fn parse_page(depth: &str, reachable: &str, kind: &str, directives: &str, aliases: &str) -> Option<Visited> {
+    let directives = directives
+        .split(',')
+        .filter(|d| !d.is_empty())
+        .map(Directive::from_name)
+        .collect::<Option<_>>()?;
+    Some(Visited {
+        depth: depth.parse().ok()?,
+        aliases: aliases.split(' ').filter(|a| !a.is_empty()).map(String::from).collect(),
+        kind: ResourceKind::from_name(kind)?,
+        directives,
+        reachable: reachable == "1",
+    })
+}
+
+/// The complete lines of a journal, and whether its last line was cut short.
+/// That line is cut from the file too, so the next record written does not run
+/// into it. Read lossily, so a bad byte only spoils its own line.
+fn read_lines(path: &Path) -> std::io::Result<(Vec<String>, bool)> {
+    let bytes = match std::fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok((Vec::new(), false)),
+        Err(e) => return Err(e),
+    };
+    let complete = bytes.iter().rposition(|&b| b == b'\n').map_or(0, |i| i + 1);
+    let torn = complete < bytes.len();
+    if torn {
+        OpenOptions::new().write(true).open(path)?.set_len(complete as u64)?;
+    }
+    let lines = String::from_utf8_lossy(&bytes[..complete]).lines().map(String::from).collect();
+    Ok((lines, torn))
+}
+
+fn append(path: &Path) -> std::io::Result<BufWriter<File>> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    Ok(BufWriter::new(file))
+}
+
+/// Tabs and line breaks would split the record, so they become spaces.
+fn write_record(log: &Mutex<BufWriter<File>>, fields: &[&str]) {
+    let line: Vec<String> = fields
+        .iter()
+        .map(|f| f.replace(['\t', '\n', '\r'], " "))
+        .collect();
+    let mut writer = log.lock().expect("state poisoned");
+    if let Err(e) = writeln!(writer, "{}", line.join("\t")).and_then(|_| writer.flush()) {
+        warn!(error = %e, "Failed to save state");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checker::CheckTarget;
+
+    /// A fresh state directory, removed when dropped.
+    struct TempDir(std::path::PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("sitemap-crawl-{}-state-{}", std::process::id(), name));
+            let _ = std::fs::remove_dir_all(&path);
+            Self(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn status(url: &str, content_type: Option<&str>) -> UrlStatus {
+        let target = CheckTarget {
+            url: url.to_string(),
+            depth: None,
+            aliases: Vec::new(),
+            kind: ResourceKind::Page,
+            directives: Vec::new(),
+            coverage: None,
+            sitemap: None,
+            fetched: None,
+        };
+        let outcome = Outcome {
+            status_code: Some(301),
+            status_text: "Moved Permanently".to_string(),
+            redirect_url: Some("https://example.com/new".to_string()),
+            content_type: content_type.map(String::from),
+            retries: 2,
+            response_time: Some(Duration::from_millis(140)),
+        };
+        UrlStatus::new(target, outcome)
+    }
+
+    #[test]
+    fn page_fields_round_trip() {
+        let page = Visited {
+            depth: 3,
+            aliases: vec!["https://example.com/a?utm_source=x".to_string(), "https://example.com/A".to_string()],
+            kind: ResourceKind::Image,
+            directives: [Directive::Nofollow, Directive::BaseHref].into(),
+            reachable: false,
+        };
+        let [depth, reachable, kind, directives, aliases] = page_fields(&page);
+        let parsed = parse_page(&depth, &reachable, &kind, &directives, &aliases).expect("parses back");
+        assert_eq!(parsed.depth, page.depth);
+        assert_eq!(parsed.aliases, page.aliases);
+        assert_eq!(parsed.kind, page.kind);
+        assert_eq!(parsed.directives, page.directives);
+        assert_eq!(parsed.reachable, page.reachable);
+
+        let [depth, reachable, kind, directives, aliases] = page_fields(&Visited::default());
+        assert!(parse_page(&depth, &reachable, &kind, &directives, &aliases).is_some());
+        assert!(parse_page("x", &reachable, &kind, &directives, &aliases).is_none());
+    }
+
+    #[test]
+    fn recorded_statuses_load_back() {
+        let dir = TempDir::new("statuses");
+        let store = StateStore::open(&dir.0).unwrap();
+        store.record_status(&status("https://example.com/old", Some("text/html; charset=utf-8")));
+        store.record_status(&status("https://example.com/gone", None));
+        drop(store);
+
+        let store = StateStore::open(&dir.0).unwrap();
+        let outcome = store.checked("https://example.com/old").expect("saved");
+        assert_eq!(outcome.status_code, Some(301));
+        assert_eq!(outcome.status_text, "Moved Permanently");
+        assert_eq!(outcome.redirect_url.as_deref(), Some("https://example.com/new"));
+        assert_eq!(outcome.content_type.as_deref(), Some("text/html; charset=utf-8"));
+        assert_eq!(outcome.retries, 2);
+        assert_eq!(outcome.response_time, Some(Duration::from_millis(140)));
+        assert_eq!(store.checked("https://example.com/gone").unwrap().content_type, None);
+    }
+
+    #[test]
+    fn a_torn_last_line_is_dropped_and_not_run_into() {
+        let dir = TempDir::new("torn");
+        let store = StateStore::open(&dir.0).unwrap();
+        store.record_status(&status("https://example.com/kept", None));
+        drop(store);
+
+        // A crash in the middle of the next record leaves a line that would
+        // otherwise parse as a six-field record.
+        let mut log = OpenOptions::new().append(true).open(dir.0.join(CHECK_LOG)).unwrap();
+        write!(log, "https://example.com/torn\t200\t\t0\t12\ttext/ht").unwrap();
+        drop(log);
+
+        let store = StateStore::open(&dir.0).unwrap();
+        assert!(store.checked("https://example.com/kept").is_some());
+        assert!(store.checked("https://example.com/torn").is_none());
+        store.record_status(&status("https://example.com/after", None));
+        drop(store);
+
+        let store = StateStore::open(&dir.0).unwrap();
+        assert!(store.checked("https://example.com/kept").is_some());
+        assert!(store.checked("https://example.com/torn").is_none());
+        assert!(store.checked("https://example.com/after").is_some());
+    }
+}
@@ -0,0 +1,173 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::config::AppConfig;
+
+/// Parse a `--max-bandwidth` value: bytes per second with an optional
+/// `K`, `M` or `G` suffix (powers of 1024), e.g. `500K` or `2M`.
+pub fn parse_bandwidth(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let upper = value.to_ascii_uppercase();
+    let digits = upper.strip_suffix('B').unwrap_or(&upper);
+    let (number, multiplier) = match digits.chars().last() {
+        Some('K') => (&digits[..digits.len() - 1], 1024),
+        Some('M') => (&digits[..digits.len() - 1], 1024 * 1024),
+        Some('G') => (&digits[..digits.len() - 1], 1024 * 1024 * 1024),
+        _ => (digits, 1),
+    };
+    let number: f64 = number
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid bandwidth `{}`, expected e.g. 500K or 2M", value))?;
+    let bytes = number * multiplier as f64;
+    if !bytes.is_finite() || bytes < 1.0 {
+        return Err(format!("bandwidth must be at least one byte per second, got `{}`", value));
+    }
+    Ok(bytes as u64)
+}
+
+/// Parse an `--rps` value: a positive, finite number of requests per second.
+pub fn parse_rps(value: &str) -> Result<f64, String> {
+    let rps: f64 = value
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid rate `{}`, expected requests per second e.g. 5 or 0.5", value))?;
+    if !rps.is_finite() || rps <= 0.0 {
+        return Err(format!("rate must be a positive number, got `{}`", value));
+    }
+    Ok(rps)
+}
+
+/// Tokens refill continuously at `rate` per second up to `burst`. Taking more
+/// than is available goes into debt, and the taker sleeps until it is paid off,
+/// so concurrent takers are served in the order they arrived.
+pub struct TokenBucket {
+    rate: f64,
+    burst: f64,
+    state: Mutex<(f64, Instant)>,
+}
+
+impl TokenBucket {
+    pub fn new(rate: f64, burst: f64) -> Self {
+        Self {
+            rate,
+            burst,
+            state: Mutex::new((burst, Instant::now())),
+        }
+    }
+
+    pub async fn take(&self, tokens: f64) {
+        let wait = self.reserve(tokens, Instant::now());
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Take `tokens` at `now`, returning how long the taker must wait.
+    fn reserve(&self, tokens: f64, now: Instant) -> Duration {
+        let mut state = self.state.lock().expect("token bucket poisoned");
+        let (available, last) = *state;
+        let refilled = (available + now.saturating_duration_since(last).as_secs_f64() * self.rate).min(self.burst);
+        let left = refilled - tokens;
+        *state = (left, now.max(last));
+        if left >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-left / self.rate)
+        }
+    }
+}
+
+/// Run-wide request rate and bandwidth limits, shared by every request the run makes.
+#[derive(Default)]
+pub struct Throttle {
+    requests: Option<TokenBucket>,
+    bytes: Option<TokenBucket>,
+}
+
+impl Throttle {
+    pub fn from_config(config: &AppConfig) -> Self {
+        Self {
+            requests: config
+                .rps
+                // No burst: requests are spaced evenly from the first one on.
+                .map(|rps| TokenBucket::new(rps, 1.0)),
+            bytes: config
+                .max_bandwidth
+                .map(|bps| TokenBucket::new(bps as f64, bps as f64)),
+        }
+    }
+
+    /// Wait for a turn to send one request.
+    pub async fn request(&self) {
+        if let Some(bucket) = &self.requests {
+            bucket.take(1.0).await;
+        }
+    }
+
+    /// Account for `len` downloaded bytes, waiting if that is over the bandwidth cap.
+    pub async fn download(&self, len: usize) {
+        if let Some(bucket) = &self.bytes {
+            bucket.take(len as f64).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[test]
+    fn bandwidth_takes_binary_suffixes() {
+        assert_eq!(parse_bandwidth("1000"), Ok(1000));
+        assert_eq!(parse_bandwidth("500K"), Ok(500 * 1024));
+        assert_eq!(parse_bandwidth("2m"), Ok(2 * 1024 * 1024));
+        assert_eq!(parse_bandwidth("1.5MB"), Ok(1536 * 1024));
+        assert_eq!(parse_bandwidth("1G"), Ok(1024 * 1024 * 1024));
+        assert_eq!(parse_bandwidth(" 64k "), Ok(64 * 1024));
+    }
+
+    #[test]
+    fn bandwidth_rejects_nothing_per_second() {
+        for value in ["", "K", "fast", "0", "-1M", "NaN", "inf", "0.1"] {
+            assert!(parse_bandwidth(value).is_err(), "{}", value);
+        }
+    }
+
+    #[test]
+    fn rps_must_be_positive_and_finite() {
+        assert_eq!(parse_rps("5"), Ok(5.0));
+        assert_eq!(parse_rps("0.5"), Ok(0.5));
+        for value in ["0", "-2", "NaN", "inf", "often"] {
+            assert!(parse_rps(value).is_err(), "{}", value);
+        }
+    }
+
+    #[test]
+    fn bucket_spends_its_burst_then_refills_at_the_rate() {
+        let bucket = TokenBucket::new(10.0, 2.0);
+        let start = Instant::now();
+        assert_eq!(bucket.reserve(1.0, start), Duration::ZERO);
+        assert_eq!(bucket.reserve(1.0, start), Duration::ZERO);
+        // Empty: the next token is 100ms away.
+        assert_eq!(bucket.reserve(1.0, start), Duration::from_millis(100));
+        // That token is owed, so the one after waits for two refills.
+        assert_eq!(bucket.reserve(1.0, start), Duration::from_millis(200));
+        // Once the debt is paid off the bucket refills, but never past its burst.
+        let later = start + Duration::from_secs(10);
+        assert_eq!(bucket.reserve(2.0, later), Duration::ZERO);
+        assert_eq!(bucket.reserve(1.0, later), Duration::from_millis(100));
+    }
+
+    #[test]
+    fn requests_are_spaced_from_the_first_one() {
+        let config = AppConfig::parse_from(["sitemap-crawl", "--rps", "5", "https://example.com/"]);
+        let throttle = Throttle::from_config(&config);
+        let bucket = throttle.requests.as_ref().expect("rate set");
+        let start = Instant::now();
+        let waits: Vec<Duration> = (0..5).map(|_| bucket.reserve(1.0, start)).collect();
+        let expected: Vec<Duration> = (0..5).map(|i| Duration::from_millis(200 * i)).collect();
+        assert_eq!(waits, expected);
+    }
+}
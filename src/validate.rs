@@ -0,0 +1,32 @@
+use std::sync::Arc;
+
+use tracing::{info, warn};
+
+use crate::config::AppConfig;
+use crate::retry::RetryPolicy;
+use crate::sitemap_parser::validate_sitemap;
+use crate::throttle::Throttle;
+
+/// Check every input URL as a sitemap against the sitemaps.org protocol,
+/// printing each violation as `file:line:column: message`. Sitemap indexes
+/// are validated along with every sitemap they lead to. Returns how many
+/// violations were found.
+pub async fn validate(config: &AppConfig, retry: &RetryPolicy, throttle: &Arc<Throttle>) -> usize {
+    let mut total = 0;
+    for sitemap_url in &config.urls {
+        info!(url = %sitemap_url, "Validating sitemap");
+        let mut violations = validate_sitemap(sitemap_url, config.proxy.as_deref(), retry, throttle);
+        let mut found = 0;
+        while let Some(violation) = violations.recv().await {
+            println!("{}", violation);
+            found += 1;
+        }
+        if found == 0 {
+            info!(url = %sitemap_url, "Sitemap is valid");
+        } else {
+            warn!(url = %sitemap_url, count = found, "Sitemap breaks the sitemap protocol");
+        }
+        total += found;
+    }
+    total
+}
@@ -0,0 +1,276 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use clap::ValueEnum;
+
+use crate::config::AppConfig;
+
+/// Independent locks per set. Workers only contend when their URLs land in
+/// the same shard.
+const SHARDS: usize = 64;
+
+/// Parse a `--bloom-fp-rate` value: a probability strictly between 0 and 1.
+pub fn parse_fp_rate(value: &str) -> Result<f64, String> {
+    let rate: f64 = value
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid rate `{}`, expected e.g. 0.001", value))?;
+    if !(rate > 0.0 && rate < 1.0) {
+        return Err(format!("rate must be between 0 and 1, got `{}`", value));
+    }
+    Ok(rate)
+}
+
+/// URLs seen so far, shared by every worker of a crawl or of the whole run.
+pub trait VisitedSet: Send + Sync {
+    /// Add `url`, returning whether it was new.
+    fn insert(&self, url: &str) -> bool;
+    fn contains(&self, url: &str) -> bool;
+    /// URLs inserted so far.
+    fn len(&self) -> usize;
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VisitedBackend {
+    /// Exact, keeps every URL string in memory
+    Memory,
+    /// Fixed memory sized by `--bloom-capacity`; a small share of new URLs may be taken for seen ones and skipped
+    Bloom,
+}
+
+/// Which `VisitedSet` a run uses and how big a Bloom filter gets.
+#[derive(Debug, Clone, Copy)]
+pub struct VisitedSettings {
+    pub backend: VisitedBackend,
+    /// URLs a Bloom filter is sized for.
+    pub capacity: usize,
+    /// Bloom false-positive rate at `capacity` URLs.
+    pub fp_rate: f64,
+}
+
+impl VisitedSettings {
+    pub fn from_config(config: &AppConfig) -> Self {
+        Self {
+            backend: config.visited_set,
+            capacity: config.bloom_capacity,
+            fp_rate: config.bloom_fp_rate,
+        }
+    }
+
+    /// A new, empty set.
+    pub fn build(&self) -> Arc<dyn VisitedSet> {
+        match self.backend {
+            VisitedBackend::Memory => Arc::new(ShardedSet::new()),
+            VisitedBackend::Bloom => Arc::new(BloomSet::new(self.capacity, self.fp_rate)),
+        }
+    }
+}
+
+impl fmt::Display for VisitedSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.backend {
+            VisitedBackend::Memory => write!(f, "in-memory ({} shards)", SHARDS),
+            VisitedBackend::Bloom => {
+                let (bits, hashes) = bloom_size(self.capacity, self.fp_rate);
+                write!(
+                    f,
+                    "bloom filter ({} URLs at {}% false positives, {} hashes, {:.1} MiB per set)",
+                    self.capacity,
+                    self.fp_rate * 100.0,
+                    hashes,
+                    bits as f64 / 8.0 / 1024.0 / 1024.0,
+                )
+            }
+        }
+    }
+}
+
+/// Hash `url` with one of two fixed seeds; the same URL always lands in the same place.
+fn hash(url: &str, seed: u64) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    seed.hash(&mut hasher);
+    url.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Exact set of URL strings, split into independently locked shards.
+pub struct ShardedSet {
+    shards: Vec<Mutex<HashSet<String>>>,
+    len: AtomicUsize,
+}
+
+impl ShardedSet {
+    pub fn new() -> Self {
+        Self {
+            shards: (0..SHARDS).map(|_| Mutex::default()).collect(),
+            len: AtomicUsize::new(0),
+        }
+    }
+
+    fn shard(&self, url: &str) -> &Mutex<HashSet<String>> {
+        &self.shards[hash(url, 0) as usize % SHARDS]
+    }
+}
+
+impl VisitedSet for ShardedSet {
+    fn insert(&self, url: &str) -> bool {
+        let mut shard = self.shard(url).lock().expect("visited shard poisoned");
+        if shard.contains(url) {
+            return false;
+        }
+        shard.insert(url.to_string());
+        self.len.fetch_add(1, Ordering::Relaxed);
+        true
+    }
+
+    fn contains(&self, url: &str) -> bool {
+        self.shard(url).lock().expect("visited shard poisoned").contains(url)
+    }
+
+    fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+}
+
+/// Bits and hash functions for `capacity` items at false-positive rate `fp_rate`.
+fn bloom_size(capacity: usize, fp_rate: f64) -> (usize, u32) {
+    let ln2 = std::f64::consts::LN_2;
+    let bits = (-(capacity.max(1) as f64) * fp_rate.ln() / (ln2 * ln2)).ceil() as usize;
+    let hashes = ((bits as f64 / capacity.max(1) as f64) * ln2).round().clamp(1.0, 16.0) as u32;
+    (bits.max(64), hashes)
+}
+
+/// Bloom filter split into locked shards, with all of a URL's bits in one
+/// shard, so checking and setting them is a single atomic step. Never says a
+/// seen URL is new, but may say a new URL was seen.
+pub struct BloomSet {
+    shards: Vec<Mutex<Vec<u64>>>,
+    /// Bits per shard.
+    shard_bits: u64,
+    hashes: u32,
+    len: AtomicUsize,
+}
+
+impl BloomSet {
+    pub fn new(capacity: usize, fp_rate: f64) -> Self {
+        let (bits, hashes) = bloom_size(capacity, fp_rate);
+        let words = bits.div_ceil(64 * SHARDS);
+        Self {
+            shards: (0..SHARDS).map(|_| Mutex::new(vec![0; words])).collect(),
+            shard_bits: words as u64 * 64,
+            hashes,
+            len: AtomicUsize::new(0),
+        }
+    }
+
+    /// The URL's shard and its bit positions in it, by double hashing.
+    fn locate(&self, url: &str) -> (usize, impl Iterator<Item = u64>) {
+        let (a, b) = (hash(url, 0), hash(url, 1));
+        let step = b | 1;
+        let shard_bits = self.shard_bits;
+        let positions = (0..self.hashes as u64).map(move |i| (a >> 6).wrapping_add(i.wrapping_mul(step)) % shard_bits);
+        (a as usize % SHARDS, positions)
+    }
+}
+
+impl VisitedSet for BloomSet {
+    fn insert(&self, url: &str) -> bool {
+        let (shard, positions) = self.locate(url);
+        let mut words = self.shards[shard].lock().expect("bloom shard poisoned");
+        let mut new = false;
+        for bit in positions {
+            let (word, mask) = ((bit / 64) as usize, 1u64 << (bit % 64));
+            new |= words[word] & mask == 0;
+            words[word] |= mask;
+        }
+        if new {
+            self.len.fetch_add(1, Ordering::Relaxed);
+        }
+        new
+    }
+
+    fn contains(&self, url: &str) -> bool {
+        let (shard, mut positions) = self.locate(url);
+        let words = self.shards[shard].lock().expect("bloom shard poisoned");
+        positions.all(|bit| words[(bit / 64) as usize] & (1u64 << (bit % 64)) != 0)
+    }
+
+    fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(i: usize) -> String {
+        format!("https://example.com/page/{}", i)
+    }
+
+    #[test]
+    fn sharded_set_inserts_each_url_once() {
+        let set = ShardedSet::new();
+        assert!(set.insert("https://example.com/"));
+        assert!(!set.insert("https://example.com/"));
+        assert!(set.insert("https://example.com/other"));
+        assert!(set.contains("https://example.com/"));
+        assert!(!set.contains("https://example.com/missing"));
+        assert_eq!(set.len(), 2);
+    }
+
+    #[test]
+    fn sharded_set_counts_concurrent_inserts_once() {
+        let set = Arc::new(ShardedSet::new());
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let set = Arc::clone(&set);
+                std::thread::spawn(move || (0..1000).filter(|&i| set.insert(&url(i))).count())
+            })
+            .collect();
+        let inserted: usize = threads.into_iter().map(|t| t.join().unwrap()).sum();
+        assert_eq!(inserted, 1000);
+        assert_eq!(set.len(), 1000);
+    }
+
+    #[test]
+    fn bloom_size_follows_the_standard_formula() {
+        // m = -n ln p / (ln 2)^2, k = m/n ln 2
+        assert_eq!(bloom_size(1_000_000, 0.01), (9_585_059, 7));
+        // About 1.8 MB per million URLs at the default rate.
+        let (bits, hashes) = bloom_size(1_000_000, 0.001);
+        assert_eq!(bits / 8 / 1000, 1797);
+        assert_eq!(hashes, 10);
+        assert_eq!(bloom_size(0, 0.5), (64, 1));
+    }
+
+    #[test]
+    fn bloom_set_never_forgets_a_url() {
+        let set = BloomSet::new(10_000, 0.01);
+        assert!(!set.contains(&url(0)));
+        assert!(set.insert(&url(0)));
+        assert!(!set.insert(&url(0)));
+        for i in 1..10_000 {
+            set.insert(&url(i));
+        }
+        assert!((0..10_000).all(|i| set.contains(&url(i))));
+        assert!(set.len() <= 10_000 && set.len() > 9_900);
+    }
+
+    #[test]
+    fn bloom_set_false_positives_stay_near_the_requested_rate() {
+        let (capacity, fp_rate) = (50_000, 0.01);
+        let set = BloomSet::new(capacity, fp_rate);
+        for i in 0..capacity {
+            set.insert(&url(i));
+        }
+        let probes = 100_000;
+        let false_positives = (capacity..capacity + probes).filter(|&i| set.contains(&url(i))).count();
+        let rate = false_positives as f64 / probes as f64;
+        assert!(rate < fp_rate * 2.0, "false-positive rate {}", rate);
+    }
+}